@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::song::SongInfo;
+
+/// A url from a batch file with the metadata its songs start with
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    pub url: String,
+    /// Run the songs of this url with another config than the rest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    /// Songinfo fields like album or year. They are merged over the fields infocopy copies.
+    /// `cover_url` embeds a cover into just these songs
+    #[serde(flatten)]
+    pub songinfo: SongInfo,
+}
+
+impl BatchEntry {
+    pub fn from_url(url: &str) -> Self {
+        BatchEntry {
+            url: url.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchFile {
+    #[serde(default)]
+    entry: Vec<BatchEntry>,
+}
+
+/// Reads a batch file, or stdin if `path` is `-`
+pub fn read_batch_file(path: &str) -> Result<Vec<BatchEntry>> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read the batch file from stdin")?;
+        text
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read batch file {path}"))?
+    };
+    parse_batch(&text).with_context(|| format!("Invalid batch file {path}"))
+}
+
+/// A batch file is either a list of urls, one per line, or a TOML file with an `[[entry]]` table
+/// for every url. Empty lines and lines starting with `#` are ignored in both
+fn parse_batch(text: &str) -> Result<Vec<BatchEntry>> {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    if !lines.peek().is_some_and(|line| line.starts_with('[')) {
+        return Ok(lines.map(BatchEntry::from_url).collect());
+    }
+
+    let file: BatchFile = toml::from_str(text)?;
+    if file.entry.is_empty() {
+        bail!("The batch file has no [[entry]] tables");
+    }
+    Ok(file.entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_batch, BatchEntry};
+
+    #[test]
+    fn test_parse_batch() {
+        let urls =
+            parse_batch("# Imports\nhttps://a.example/1\n\n  https://a.example/2\n").unwrap();
+        assert_eq!(
+            vec![
+                BatchEntry::from_url("https://a.example/1"),
+                BatchEntry::from_url("https://a.example/2")
+            ],
+            urls
+        );
+
+        let entries = parse_batch(
+            r#"
+[[entry]]
+url = "https://artist.bandcamp.com/album/x"
+album = "X"
+year = 2019
+cover_url = "https://example.com/x.jpg"
+config = "bandcamp"
+
+[[entry]]
+url = "https://youtu.be/y"
+"#,
+        )
+        .unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Some("bandcamp"), entries[0].config.as_deref());
+        assert_eq!(Some(2019), entries[0].songinfo.year);
+        assert_eq!(Some(String::from("X")), entries[0].songinfo.album);
+        assert!(entries[0].songinfo.get_field("cover_url").is_some());
+        assert_eq!(BatchEntry::from_url("https://youtu.be/y"), entries[1]);
+
+        assert!(parse_batch("[[entry]]\nurl = \"https://a.example\"\nyear = \"soon\"\n").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fmt,
+    process::{Child, ExitStatus},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+
+/// Tells modules that the run should stop, for example because Ctrl-C was pressed.
+/// Clones share the same flag
+#[derive(Clone, Debug, Default)]
+pub struct CancelFlag {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a [`Cancelled`] error if the run was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Waits for a child process to exit. If the run is cancelled in the meantime the process is killed
+    /// and a [`Cancelled`] error is returned
+    pub fn wait_for(&self, child: &mut Child) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if self.is_cancelled() {
+                // The process might have exited on its own already
+                let _ = child.kill();
+                child.wait()?;
+                return Err(Cancelled.into());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// The error modules return when they stop because the run was cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
 use lexopt::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{batch::BatchEntry, help, modules::ModuleRegistry};
 
 const HELP: &str = r#"
 musicfetch
@@ -10,22 +12,87 @@ Usage:
     musicfetch <url>...
     musicfetch (-c | --cover_url) <cover_url> <url>
     musicfetch (-o | --output_dir) <output_dir> <url>
-    musicfetch -? | -h | --help
+    musicfetch --dry-run <url>...
+    musicfetch --batch-file (<file> | -) [<url>...]
+    musicfetch --local <file or directory>...
+    musicfetch --non-interactive [--album <album>] [--artist <artist>] [--year <year>] [--genre <genre>] <url>...
+    musicfetch --resume <state>
+    musicfetch --events json --events-file <file> <url>...
+    musicfetch modules
+    musicfetch config (list | show [<name>] [--effective] | path | init [--force] | diff-default)
+    musicfetch -? | -h | --help [<module>]
 
 Options:
-    -? -h --help        Show this help
-    -v --version        Print version and exit
+    -? -h --help        Show this help, or the settings of a module if one is given
+    -V --version        Print version and exit
+    -v --verbose        Log more. Can be given more than once
+    -q --quiet          Log less. Can be given more than once
+    --log-file          Append the log to this file instead of writing it to stderr
     -c --cover_url      Specify the url of the cover that should be added to the songs
     -o --output_dir     Specify the directory the songs should be downloaded to
     -C --config         Use the config with this name
+    --set               Override a setting of the config, like --set module.rename.copy=true. Can be given more than once
+    --debug-config      Print the config files and overrides that make up the config, in the order they are applied
+    --dry-run           Fetch the song info and show the UIs, but only print what would be downloaded, tagged and moved
+    --batch-file        Read urls from this file, or from stdin with -. See the README for setting metadata per url
+    --local             Retag and rename audio files on disk instead of downloading. Their tags are read into the songinfo
+    --non-interactive   Don't open the UIs. Songs keep the metadata they have, songs missing required fields fail
+    --album             Set the album of all songs. Also --artist, --year and --genre
+    --resume            Continue a run that failed from the state file it left behind
+    --events            Report the progress in this format. Only json is supported, which writes one JSON object per line
+    --events-file       Where to write the events to. Either a file or an open file descriptor like fd:3
+
+Config commands:
+    list                List the configs in the config directory
+    show                Print a config. With --effective the configs it extends and the --set overrides are merged in
+    path                Print the directory the configs are loaded from
+    init                Write the built-in default config to default.toml. With --force an existing one is replaced
+    diff-default        Show the settings in your default.toml that differ from the built-in default
 "#;
 
-#[derive(Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Args {
-    urls: Vec<String>,
-    cover_url: Option<String>,
-    output_dir: Option<PathBuf>,
+    pub urls: Vec<String>,
+    /// Audio files and directories on disk to use instead of downloading songs
+    pub files: Vec<PathBuf>,
+    pub cover_url: Option<String>,
+    pub output_dir: Option<PathBuf>,
     pub config: Option<String>,
+    /// Overrides for config keys, like `module.rename.copy=true`
+    pub set: Vec<String>,
+    pub debug_config: bool,
+    pub dry_run: bool,
+    pub resume: Option<PathBuf>,
+    /// Number of -v minus number of -q flags
+    pub verbosity: i8,
+    pub log_file: Option<PathBuf>,
+    /// A file or `fd:<n>` to write the events of the run to as JSON lines
+    pub events_file: Option<String>,
+    /// Urls from a batch file with the metadata for their songs. Fetched after `urls`
+    pub batch: Vec<BatchEntry>,
+    /// Don't open any UI, the UI modules keep the songinfo as it is
+    pub non_interactive: bool,
+    /// Album fields for all songs, used where the batch file doesn't set them
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub year: Option<u64>,
+    pub genre: Option<String>,
+    /// The batch file to read `batch` from, or `-` for stdin
+    #[serde(skip)]
+    pub batch_file: Option<String>,
+    /// Set for `musicfetch config ...`, which manages the configs instead of downloading anything
+    #[serde(skip)]
+    pub config_command: Option<ConfigCommand>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigCommand {
+    List,
+    Show { effective: bool },
+    Path,
+    Init { force: bool },
+    DiffDefault,
 }
 
 pub fn parse_args() -> Result<Args, lexopt::Error> {
@@ -33,9 +100,30 @@ pub fn parse_args() -> Result<Args, lexopt::Error> {
     let mut cover_url: Option<String> = None;
     let mut output_dir: Option<PathBuf> = None;
     let mut config: Option<String> = None;
+    let mut set = vec![];
+    let mut debug_config = false;
+    let mut dry_run = false;
+    let mut resume: Option<PathBuf> = None;
+    let mut verbosity: i8 = 0;
+    let mut log_file: Option<PathBuf> = None;
+    let mut events_format: Option<String> = None;
+    let mut events_file: Option<String> = None;
+    let mut batch_file: Option<String> = None;
+    let mut non_interactive = false;
+    let mut local = false;
+    let mut album: Option<String> = None;
+    let mut artist: Option<String> = None;
+    let mut year: Option<u64> = None;
+    let mut genre: Option<String> = None;
+    let mut config_action: Option<Option<String>> = None;
+    let mut effective = false;
+    let mut force = false;
 
     let mut parser = lexopt::Parser::from_env();
+    let mut first_arg = true;
     while let Some(arg) = parser.next()? {
+        // Subcommands are only recognized as the first argument, so `--local config` is a directory
+        let is_first_arg = std::mem::replace(&mut first_arg, false);
         match arg {
             Short('c') | Long("cover_url") => {
                 cover_url = Some(parser.value()?.parse()?);
@@ -43,6 +131,29 @@ pub fn parse_args() -> Result<Args, lexopt::Error> {
             Short('o') | Long("output_dir") => {
                 output_dir = Some(parser.value()?.parse()?);
             }
+            Value(val) if is_first_arg && val == "config" => {
+                config_action = Some(None);
+            }
+            Value(val) if matches!(config_action, Some(None)) => {
+                config_action = Some(Some(val.string()?));
+            }
+            Value(val) if config_action.is_some() => {
+                // `config show <name>`
+                config = Some(val.string()?);
+            }
+            Long("effective") => {
+                effective = true;
+            }
+            Long("force") => {
+                force = true;
+            }
+            Value(val) if is_first_arg && val == "modules" => {
+                print!(
+                    "{}",
+                    help::module_list(&ModuleRegistry::with_builtin_modules())
+                );
+                std::process::exit(0);
+            }
             Value(val) => {
                 urls.push(
                     val.into_string()
@@ -52,21 +163,150 @@ pub fn parse_args() -> Result<Args, lexopt::Error> {
             Short('C') | Long("config") => {
                 config = Some(parser.value()?.parse()?);
             }
+            Long("set") => {
+                set.push(parser.value()?.parse()?);
+            }
+            Long("debug-config") => {
+                debug_config = true;
+            }
+            Long("dry-run") => {
+                dry_run = true;
+            }
+            Long("resume") => {
+                resume = Some(parser.value()?.parse()?);
+            }
             Short('h') | Short('?') | Long("help") => {
-                println!("{}", HELP);
-                std::process::exit(0);
+                let module = parser
+                    .raw_args()?
+                    .next_if(|arg| !arg.to_string_lossy().starts_with('-'));
+                let Some(module) = module else {
+                    println!("{}", HELP);
+                    std::process::exit(0);
+                };
+
+                let registry = ModuleRegistry::with_builtin_modules();
+                match help::module_help(&registry, &module.to_string_lossy()) {
+                    Ok(module_help) => {
+                        print!("{module_help}");
+                        std::process::exit(0);
+                    }
+                    Err(error) => return Err(error.to_string().into()),
+                }
+            }
+            Short('v') | Long("verbose") => {
+                verbosity = verbosity.saturating_add(1);
             }
-            Short('v') | Long("version") => {
+            Short('q') | Long("quiet") => {
+                verbosity = verbosity.saturating_sub(1);
+            }
+            Long("log-file") => {
+                log_file = Some(parser.value()?.parse()?);
+            }
+            Long("events") => {
+                let format: String = parser.value()?.parse()?;
+                if format != "json" {
+                    return Err(
+                        format!("Unknown event format '{format}'. Only json is supported").into(),
+                    );
+                }
+                events_format = Some(format);
+            }
+            Long("events-file") => {
+                events_file = Some(parser.value()?.parse()?);
+            }
+            Long("batch-file") => {
+                batch_file = Some(parser.value()?.parse()?);
+            }
+            Long("local") => {
+                local = true;
+            }
+            Long("non-interactive") => {
+                non_interactive = true;
+            }
+            Long("album") => {
+                album = Some(parser.value()?.parse()?);
+            }
+            Long("artist") => {
+                artist = Some(parser.value()?.parse()?);
+            }
+            Long("year") => {
+                year = Some(parser.value()?.parse()?);
+            }
+            Long("genre") => {
+                genre = Some(parser.value()?.parse()?);
+            }
+            Short('V') | Long("version") => {
                 println!("{}", env!("CARGO_PKG_VERSION"));
                 std::process::exit(0);
             }
             _ => return Err(arg.unexpected()),
         }
     }
+    if events_format.is_some() != events_file.is_some() {
+        return Err(String::from("--events and --events-file have to be given together").into());
+    }
+    if batch_file.is_some() && resume.is_some() {
+        return Err(String::from(
+            "--batch-file can't be used with --resume, the resumed run keeps its urls",
+        )
+        .into());
+    }
+
+    // With --local the arguments are paths instead of urls
+    let (urls, files) = if local {
+        (vec![], urls.into_iter().map(PathBuf::from).collect())
+    } else {
+        (urls, vec![])
+    };
+
+    let config_command = match config_action {
+        None if effective || force => {
+            return Err(
+                String::from("--effective and --force only work with `musicfetch config`").into(),
+            )
+        }
+        None => None,
+        Some(action) => Some(match action.as_deref() {
+            Some("list") => ConfigCommand::List,
+            Some("show") => ConfigCommand::Show { effective },
+            Some("path") => ConfigCommand::Path,
+            Some("init") => ConfigCommand::Init { force },
+            Some("diff-default") => ConfigCommand::DiffDefault,
+            Some(action) => {
+                return Err(format!(
+                    "Unknown config command '{action}'. Use list, show, path, init or diff-default"
+                )
+                .into())
+            }
+            None => {
+                return Err(String::from(
+                    "Missing config command. Use list, show, path, init or diff-default",
+                )
+                .into())
+            }
+        }),
+    };
+
     Ok(Args {
         urls,
+        files,
         cover_url,
         output_dir,
-        config
+        config,
+        set,
+        debug_config,
+        dry_run,
+        resume,
+        verbosity,
+        log_file,
+        events_file,
+        batch: vec![],
+        batch_file,
+        non_interactive,
+        album,
+        artist,
+        year,
+        genre,
+        config_command,
     })
 }
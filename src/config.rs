@@ -1,18 +1,123 @@
 use std::{
+    collections::BTreeMap,
     env,
     fs::{self, create_dir_all, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use anyhow::{bail, Context, Result};
 use home::home_dir;
-use log::info;
-use serde_json::Value;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-const DEFAULT_CONFIG: &[u8] = include_bytes!("../config/default.toml");
+use crate::{config_sources::ConfigSources, profiles::ProfileConfig};
 
-pub fn get_config(name: &str) -> Result<Value> {
+/// The default config that is built into the binary
+pub const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// The modules to run. Musicfetch puts these into stages on its own
+    #[serde(default)]
+    pub modules: Vec<String>,
+    /// Explicit stages. If this is set `modules` is ignored
+    #[serde(default)]
+    pub stages: BTreeMap<String, Vec<String>>,
+    /// Settings for each module, keyed by module name
+    #[serde(default)]
+    pub module: Map<String, Value>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Configs to use for urls from certain sites, keyed by config name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// Where each setting was read from, so problems can be pointed out in the file
+    #[serde(skip)]
+    pub sources: ConfigSources,
+}
+
+/// The `[logging]` section of the config
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// off, error, warn, info, debug or trace. Defaults to info
+    pub level: Option<String>,
+    /// File to append the log to. Without one the log is written to stderr
+    pub file: Option<PathBuf>,
+    /// Log levels for single modules, keyed by module or instance name
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn module_config(&self, module_name: &str) -> Option<&Value> {
+        self.module.get(module_name)
+    }
+
+    /// Applies overrides like `module.rename.template="%(title).%(ext)"` on top of the config.
+    ///
+    /// Values are written like in a config file. Values that are not valid TOML are used as strings,
+    /// so the quotes can be left out. Lists can be changed with `{ append = [...], remove = [...] }`
+    /// like in a config that `extends` another one
+    pub fn apply_overrides(&mut self, overrides: &[String]) -> Result<()> {
+        let mut config_value = serde_json::to_value(&*self)?;
+
+        for assignment in overrides {
+            let (path, value) = parse_override(assignment)?;
+
+            let overlay = path.iter().rev().fold(value, |value, key| {
+                Value::Object(Map::from_iter([(key.to_owned(), value)]))
+            });
+            merge_config_values(&mut config_value, overlay);
+            // The value doesn't come from the file anymore
+            self.sources.remove(&path);
+        }
+
+        let sources = std::mem::take(&mut self.sources);
+        *self = serde_json::from_value(config_value)
+            .context("The config is invalid after applying the overrides")?;
+        self.sources = sources;
+        Ok(())
+    }
+}
+
+/// Splits an override like `module.rename.copy=true` into the path of the key and the value
+fn parse_override(assignment: &str) -> Result<(Vec<String>, Value)> {
+    let invalid = || {
+        format!("Invalid override '{assignment}'. It has to look like module.rename.template=\"%(title).%(ext)\"")
+    };
+
+    let (key, value) = assignment.split_once('=').with_context(invalid)?;
+    // The key is parsed by TOML and followed down the tables it creates
+    let mut path = vec![];
+    let mut key_table =
+        toml::from_str::<toml::Table>(&format!("{key} = 0")).with_context(invalid)?;
+    loop {
+        let mut entries = key_table.into_iter();
+        let (Some((key, value)), None) = (entries.next(), entries.next()) else {
+            bail!(invalid());
+        };
+        path.push(key);
+        let toml::Value::Table(table) = value else {
+            break;
+        };
+        key_table = table;
+    }
+
+    let value = match toml::from_str::<toml::Table>(&format!("value = {value}")) {
+        Ok(mut table) => serde_json::to_value(table.remove("value"))?,
+        Err(_) => Value::String(value.to_owned()),
+    };
+    Ok((path, value))
+}
+
+/// The name of the config files that are picked up from the current directory and its parents
+pub const PROJECT_CONFIG_NAME: &str = ".musicfetch.toml";
+
+/// Loads the config with this name from the config directory and merges the project configs
+/// for the current directory over it. See [`find_project_configs`]
+pub fn get_config(name: &str) -> Result<Config> {
     let dir = match get_config_dir() {
         Some(dir) => dir,
         None => {
@@ -20,10 +125,26 @@ pub fn get_config(name: &str) -> Result<Value> {
             create_default_config()?
         }
     };
-    get_config_by_name(name, dir)
+    let project_configs = env::current_dir()
+        .map(|dir| find_project_configs(&dir))
+        .unwrap_or_default();
+    get_layered_config(name, &dir, &project_configs)
 }
 
-fn create_default_config() -> Result<PathBuf> {
+/// Finds the `.musicfetch.toml` files in `dir` and its parents. The one furthest up comes first,
+/// so when they are merged in this order the one closest to `dir` wins
+pub fn find_project_configs(dir: &Path) -> Vec<PathBuf> {
+    let mut configs: Vec<PathBuf> = dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .filter(|path| path.is_file())
+        .collect();
+    configs.reverse();
+    configs
+}
+
+/// The directory the configs of the user belong in, whether it exists or not
+pub fn user_config_dir() -> Result<PathBuf> {
     let mut config_dir = match env::var("XDG_CONFIG_HOME") {
         Ok(dir) => PathBuf::from(dir),
         Err(_) => match home_dir() {
@@ -35,6 +156,11 @@ fn create_default_config() -> Result<PathBuf> {
         },
     };
     config_dir.push("musicfetch");
+    Ok(config_dir)
+}
+
+fn create_default_config() -> Result<PathBuf> {
+    let config_dir = user_config_dir()?;
 
     create_dir_all(&config_dir)?;
 
@@ -43,24 +169,133 @@ fn create_default_config() -> Result<PathBuf> {
 
     let mut config_file = File::create(&config_path)?;
 
-    config_file.write_all(DEFAULT_CONFIG)?;
+    config_file.write_all(DEFAULT_CONFIG.as_bytes())?;
 
     Ok(config_dir)
 }
 
-fn get_config_by_name(name: &str, dir: PathBuf) -> Result<Value> {
-    let mut file_path = dir;
-    file_path.push(format!("{}.toml", name));
+/// Loads a config from `dir` with the configs it extends merged in, but without checking that it is valid
+pub fn get_config_value(name: &str, dir: &Path) -> Result<Value> {
+    let (config_value, _) = load_config_value(name, dir, &mut vec![])?;
+    Ok(config_value)
+}
+
+/// Loads a config with the configs it extends and merges `project_configs` over it
+fn get_layered_config(name: &str, dir: &Path, project_configs: &[PathBuf]) -> Result<Config> {
+    let (mut config_value, mut sources) = load_config_value(name, dir, &mut vec![])?;
 
-    let file_path_string = file_path.clone().to_string_lossy().to_string();
+    for path in project_configs {
+        let (project_value, project_sources) = read_config_file(path)?;
+        if project_value.get("extends").is_some() {
+            bail!(
+                "{} can't use 'extends'. Choose the config it builds on with -C instead",
+                path.display()
+            );
+        }
+        merge_config_values(&mut config_value, project_value);
+        sources.merge(project_sources);
+    }
 
-    let config_string = fs::read_to_string(file_path).context(file_path_string)?;
-    let config = toml::from_str::<Value>(&config_string)?;
+    let mut config = serde_json::from_value::<Config>(config_value)
+        .with_context(|| format!("Invalid config {}", name))?;
+    config.sources = sources;
 
     Ok(config)
 }
 
-fn get_config_dir() -> Option<PathBuf> {
+fn read_config_file(path: &Path) -> Result<(Value, ConfigSources)> {
+    let config_string = fs::read_to_string(path).context(path.display().to_string())?;
+    let toml_value = toml::from_str::<toml::Value>(&config_string)
+        .with_context(|| format!("Invalid config {}", path.display()))?;
+
+    Ok((
+        serde_json::to_value(toml_value)?,
+        ConfigSources::read(path, &config_string),
+    ))
+}
+
+/// Reads a config file and merges it over the config it `extends`, if any.
+/// `chain` holds the configs that are already being loaded to detect loops
+fn load_config_value(
+    name: &str,
+    dir: &Path,
+    chain: &mut Vec<String>,
+) -> Result<(Value, ConfigSources)> {
+    if chain.iter().any(|n| n == name) {
+        bail!(
+            "Config {} extends itself: {} -> {}",
+            name,
+            chain.join(" -> "),
+            name
+        );
+    }
+    chain.push(name.to_owned());
+
+    let mut file_path = dir.to_owned();
+    file_path.push(format!("{}.toml", name));
+
+    let (mut config_value, sources) = read_config_file(&file_path)?;
+
+    let extends = config_value
+        .as_object_mut()
+        .and_then(|config| config.remove("extends"));
+
+    let (mut base, mut base_sources) = match extends {
+        None => (Value::Object(Map::new()), ConfigSources::default()),
+        Some(Value::String(parent)) => load_config_value(&parent, dir, chain)
+            .with_context(|| format!("Failed to load config {} extended by {}", parent, name))?,
+        Some(_) => bail!(
+            "'extends' in config {} has to be the name of a config",
+            name
+        ),
+    };
+    merge_config_values(&mut base, config_value);
+    base_sources.merge(sources);
+
+    Ok((base, base_sources))
+}
+
+/// Merges `overlay` into `base`. Tables are merged key by key, all other values are replaced.
+///
+/// A table like `{ append = [...], remove = [...] }` over a list changes the list instead of replacing it.
+/// The values in `remove` are taken out first, then the ones in `append` are added to the end
+fn merge_config_values(base: &mut Value, overlay: Value) {
+    match overlay {
+        Value::Object(operation)
+            if is_list_operation(&operation) && (base.is_array() || base.is_null()) =>
+        {
+            let mut list = base.as_array().cloned().unwrap_or_default();
+            if let Some(Value::Array(remove)) = operation.get("remove") {
+                list.retain(|value| !remove.contains(value));
+            }
+            if let Some(Value::Array(append)) = operation.get("append") {
+                list.extend(append.iter().cloned());
+            }
+            *base = Value::Array(list);
+        }
+        Value::Object(overlay) if base.is_object() || base.is_null() => {
+            if base.is_null() {
+                *base = Value::Object(Map::new());
+            }
+            if let Value::Object(base) = base {
+                for (key, value) in overlay {
+                    merge_config_values(base.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        overlay => *base = overlay,
+    }
+}
+
+fn is_list_operation(table: &Map<String, Value>) -> bool {
+    !table.is_empty()
+        && table
+            .iter()
+            .all(|(key, value)| (key == "append" || key == "remove") && value.is_array())
+}
+
+/// The directory configs are loaded from. That is the user config directory or, if it doesn't exist, a global one
+pub fn get_config_dir() -> Option<PathBuf> {
     get_user_config_dir().or_else(get_global_config_dir)
 }
 
@@ -70,7 +305,7 @@ fn get_user_config_dir() -> Option<PathBuf> {
         path.push("musicfetch");
 
         if path.is_absolute() && path.exists() {
-            info!(
+            debug!(
                 "Found path using XDG_CONFIG_HOME: {}",
                 path.to_string_lossy()
             );
@@ -83,7 +318,7 @@ fn get_user_config_dir() -> Option<PathBuf> {
         path.push("musicfetch");
 
         if path.is_absolute() && path.exists() {
-            info!(
+            debug!(
                 "Found path using home directory: {}",
                 path.to_string_lossy()
             );
@@ -91,7 +326,7 @@ fn get_user_config_dir() -> Option<PathBuf> {
         }
     }
 
-    log::info!("Failed to find user config directory. Using global config");
+    log::debug!("Failed to find user config directory. Using global config");
     None
 }
 
@@ -110,3 +345,151 @@ fn get_global_config_dir() -> Option<PathBuf> {
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use serde_json::json;
+
+    use crate::{modules::ModuleRegistry, validate::validate_config};
+
+    use super::{find_project_configs, get_layered_config, merge_config_values, Config};
+
+    #[test]
+    fn test_merge_config_values() {
+        let mut base = json!({
+            "modules": ["fetch_song_info", "infocopy", "tagui", "download", "rename"],
+            "stages": { "stage1": ["fetch_song_info"], "stage2": ["download"] },
+            "module": {
+                "rename": { "template": "%(title).%(ext)" },
+                "download": { "yt_dlp_args": [] },
+            },
+        });
+
+        merge_config_values(
+            &mut base,
+            json!({
+                "modules": { "remove": ["tagui"], "append": ["albumcover"] },
+                "stages": { "stage2": { "append": ["trackcounter"] }, "stage3": ["rename"] },
+                "module": { "rename": { "template": "%(artist)/%(title).%(ext)" } },
+            }),
+        );
+
+        assert_eq!(
+            json!({
+                "modules": ["fetch_song_info", "infocopy", "download", "rename", "albumcover"],
+                "stages": {
+                    "stage1": ["fetch_song_info"],
+                    "stage2": ["download", "trackcounter"],
+                    "stage3": ["rename"],
+                },
+                "module": {
+                    "rename": { "template": "%(artist)/%(title).%(ext)" },
+                    "download": { "yt_dlp_args": [] },
+                },
+            }),
+            base
+        );
+    }
+
+    #[test]
+    fn test_config_sources() {
+        let dir = env::temp_dir().join(format!("musicfetch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("base.toml"),
+            "modules = [\"fetch_song_info\", \"download\", \"rename\"]\n\n[module.rename]\ntemplate = \"%(title)\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("child.toml"),
+            "extends = \"base\"\n\n[module.rename]\ntemplate = 5\n\n[module.renam]\ncopy = true\n",
+        )
+        .unwrap();
+
+        let project_dir = dir.join("library").join("album");
+        fs::create_dir_all(&project_dir).unwrap();
+        fs::write(
+            dir.join("library").join(".musicfetch.toml"),
+            "[module.rename]\ncopy = true\n",
+        )
+        .unwrap();
+        fs::write(
+            project_dir.join(".musicfetch.toml"),
+            "modules = { append = [\"tag_files\"] }\n",
+        )
+        .unwrap();
+
+        let project_configs = find_project_configs(&project_dir);
+        let config = get_layered_config("child", &dir, &project_configs);
+        let _ = fs::remove_dir_all(&dir);
+        let config = config.unwrap();
+
+        assert_eq!(
+            vec![
+                dir.join("base.toml"),
+                dir.join("child.toml"),
+                dir.join("library").join(".musicfetch.toml"),
+                project_dir.join(".musicfetch.toml"),
+            ],
+            config.sources.files()
+        );
+        assert_eq!(
+            vec!["fetch_song_info", "download", "rename", "tag_files"],
+            config.modules
+        );
+        assert_eq!(
+            json!({ "template": 5, "copy": true }),
+            config.module["rename"]
+        );
+
+        let modules = config.sources.value(&["modules"]).unwrap();
+        assert_eq!(
+            (project_dir.join(".musicfetch.toml"), 1, 11),
+            (modules.file.clone(), modules.line, modules.column)
+        );
+
+        let error = validate_config(&config, &ModuleRegistry::with_builtin_modules())
+            .unwrap_err()
+            .to_string();
+        let child = dir.join("child.toml").display().to_string();
+        assert!(error.contains(&format!(
+            "{child}:4:12: Setting 'template' of module rename"
+        )));
+        assert!(error.contains(&format!("{child}:6:9: Settings for unknown module")));
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let mut config: Config = serde_json::from_value(json!({
+            "stages": { "stage1": ["fetch_song_info"], "stage2": ["download", "tagui"] },
+            "module": { "rename": { "template": "%(title).%(ext)" } },
+        }))
+        .unwrap();
+
+        config
+            .apply_overrides(&[
+                String::from("module.rename.template=%(artist)/%(title).%(ext)"),
+                String::from("module.\"rename@backup\".copy = true"),
+                String::from("stages.stage2={ remove = [\"tagui\"], append = [\"rename\"] }"),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            json!({
+                "rename": { "template": "%(artist)/%(title).%(ext)" },
+                "rename@backup": { "copy": true },
+            }),
+            serde_json::to_value(&config.module).unwrap()
+        );
+        assert_eq!(vec!["download", "rename"], config.stages["stage2"]);
+
+        assert!(config
+            .apply_overrides(&[String::from("module.rename")])
+            .is_err());
+        assert!(config
+            .apply_overrides(&[String::from("module.rename template=x")])
+            .is_err());
+    }
+}
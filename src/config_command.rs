@@ -0,0 +1,236 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::{
+    cmdline::{Args, ConfigCommand},
+    config::{self, Config, DEFAULT_CONFIG},
+};
+
+/// Runs one of the `musicfetch config` commands
+pub fn run(command: &ConfigCommand, args: &Args) -> Result<()> {
+    let name = args.config.as_deref().unwrap_or("default");
+
+    match command {
+        ConfigCommand::List => list(&existing_config_dir()?),
+        ConfigCommand::Show { effective: false } => {
+            let path = existing_config_dir()?.join(format!("{name}.toml"));
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            print!("{text}");
+            Ok(())
+        }
+        ConfigCommand::Show { effective: true } => {
+            let mut config = config::get_config(name).context("Failed to load config")?;
+            config.apply_overrides(&args.set)?;
+            if args.debug_config {
+                print_config_layers(&config, &args.set);
+            }
+            print!("{}", to_toml(serde_json::to_value(&config)?)?);
+            Ok(())
+        }
+        ConfigCommand::Path => {
+            println!("{}", existing_config_dir()?.display());
+            Ok(())
+        }
+        ConfigCommand::Init { force } => init(*force),
+        ConfigCommand::DiffDefault => {
+            let yours = config::get_config_value("default", &existing_config_dir()?)?;
+            let builtin = toml::from_str::<toml::Value>(DEFAULT_CONFIG)?;
+            let differences = diff_configs(&serde_json::to_value(builtin)?, &yours);
+
+            if differences.is_empty() {
+                println!("Your default config has the same settings as the built-in one");
+            } else {
+                println!("- only in the built-in default, + only in yours, ~ changed");
+            }
+            for difference in differences {
+                println!("{difference}");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints what the config was put together from, in the order it was applied
+pub fn print_config_layers(config: &Config, overrides: &[String]) {
+    eprintln!("Config layers, later ones override earlier ones:");
+    for (i, file) in config.sources.files().iter().enumerate() {
+        eprintln!("  {}. {}", i + 1, file.display());
+    }
+    let files = config.sources.files().len();
+    for (i, assignment) in overrides.iter().enumerate() {
+        eprintln!("  {}. --set {assignment}", files + i + 1);
+    }
+}
+
+fn existing_config_dir() -> Result<PathBuf> {
+    match config::get_config_dir() {
+        Some(dir) => Ok(dir),
+        None => {
+            bail!("There is no config directory yet. Run `musicfetch config init` to create one")
+        }
+    }
+}
+
+fn list(dir: &Path) -> Result<()> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            names.push(path);
+        }
+    }
+    names.sort();
+
+    for path in names {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extends = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| toml::from_str::<toml::Table>(&text).ok())
+            .and_then(|table| table.get("extends")?.as_str().map(str::to_owned));
+
+        match extends {
+            Some(parent) => println!("{name} (extends {parent})"),
+            None => println!("{name}"),
+        }
+    }
+
+    let project_configs = env::current_dir()
+        .map(|dir| config::find_project_configs(&dir))
+        .unwrap_or_default();
+    if !project_configs.is_empty() {
+        println!("\nProject configs used in this directory:");
+        for path in project_configs {
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Writes the built-in default config to the user config directory
+fn init(force: bool) -> Result<()> {
+    let dir = config::user_config_dir()?;
+    let path = dir.join("default.toml");
+
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if existing == DEFAULT_CONFIG {
+            println!("{} is already the built-in default", path.display());
+            return Ok(());
+        }
+        if !force {
+            bail!(
+                "{} already exists. Use `musicfetch config init --force` to replace it",
+                path.display()
+            );
+        }
+
+        let backup = dir.join("default.toml.bak");
+        fs::rename(&path, &backup)
+            .with_context(|| format!("Failed to move the old config to {}", backup.display()))?;
+        println!("Moved the old config to {}", backup.display());
+    }
+
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&path, DEFAULT_CONFIG)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Wrote the default config to {}", path.display());
+    Ok(())
+}
+
+/// Prints a config as TOML. Unset values and empty tables are left out
+fn to_toml(mut value: Value) -> Result<String> {
+    remove_empty(&mut value);
+    Ok(toml::to_string_pretty(&value)?)
+}
+
+fn remove_empty(value: &mut Value) {
+    if let Value::Object(table) = value {
+        for value in table.values_mut() {
+            remove_empty(value);
+        }
+        table.retain(|_, value| {
+            !value.is_null() && !value.as_object().is_some_and(|table| table.is_empty())
+        });
+    }
+}
+
+/// Lists the settings that differ between two configs, one line each
+fn diff_configs(builtin: &Value, yours: &Value) -> Vec<String> {
+    let mut builtin_settings = BTreeMap::new();
+    flatten(builtin, String::new(), &mut builtin_settings);
+    let mut your_settings = BTreeMap::new();
+    flatten(yours, String::new(), &mut your_settings);
+
+    let mut differences = vec![];
+    for (key, builtin_value) in &builtin_settings {
+        match your_settings.get(key) {
+            None => differences.push(format!("- {key} = {builtin_value}")),
+            Some(your_value) if your_value != builtin_value => differences.push(format!(
+                "~ {key} = {your_value} (built-in: {builtin_value})"
+            )),
+            Some(_) => {}
+        }
+    }
+    for (key, your_value) in &your_settings {
+        if !builtin_settings.contains_key(key) {
+            differences.push(format!("+ {key} = {your_value}"));
+        }
+    }
+    differences
+}
+
+/// Collects the values that are not tables by their dotted key
+fn flatten(value: &Value, prefix: String, settings: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(value, key, settings);
+            }
+        }
+        value => {
+            settings.insert(prefix, value.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::diff_configs;
+
+    #[test]
+    fn test_diff_configs() {
+        let builtin = json!({
+            "modules": ["fetch_song_info", "download"],
+            "module": { "rename": { "template": "%(title).%(ext)", "copy": false } },
+        });
+        let yours = json!({
+            "modules": ["fetch_song_info", "download"],
+            "module": {
+                "rename": { "template": "%(artist)/%(title).%(ext)" },
+                "download": { "yt_dlp_args": [] },
+            },
+        });
+
+        assert_eq!(
+            vec![
+                "- module.rename.copy = false",
+                "~ module.rename.template = \"%(artist)/%(title).%(ext)\" (built-in: \"%(title).%(ext)\")",
+                "+ module.download.yt_dlp_args = []",
+            ],
+            diff_configs(&builtin, &yours)
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use toml_edit::{ImDocument, TableLike};
+
+/// A position in a config file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// Where the keys of a config and their values are written, by their path like `["module", "rename", "template"]`.
+/// If a key is set in more than one file of an `extends` chain the location of the one that is used is kept
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSources {
+    keys: BTreeMap<Vec<String>, (Location, Location)>,
+    files: Vec<PathBuf>,
+}
+
+impl ConfigSources {
+    /// The files the config was read from, in the order they were merged
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Where the key at `path` is written. For tables this is the name in the `[table]` header
+    pub fn key(&self, path: &[&str]) -> Option<&Location> {
+        self.get(path).map(|(key, _)| key)
+    }
+
+    /// Where the value of the key at `path` starts
+    pub fn value(&self, path: &[&str]) -> Option<&Location> {
+        self.get(path).map(|(_, value)| value)
+    }
+
+    fn get(&self, path: &[&str]) -> Option<&(Location, Location)> {
+        let path: Vec<String> = path.iter().map(|key| key.to_string()).collect();
+        self.keys.get(&path)
+    }
+
+    /// Finds the keys in the text of a valid TOML file. Keys inside arrays, like the ones of
+    /// `[[table]]` arrays, are not looked at. Problems with them are pointed out at the array
+    pub fn read(file: &Path, text: &str) -> Self {
+        let mut sources = ConfigSources {
+            files: vec![file.to_owned()],
+            ..Default::default()
+        };
+        let Ok(document) = ImDocument::parse(text) else {
+            return sources;
+        };
+        let location = |offset: usize| {
+            let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+            Location {
+                file: file.to_owned(),
+                line: text[..offset].matches('\n').count() + 1,
+                column: text[line_start..offset].chars().count() + 1,
+            }
+        };
+        sources.insert_table(&mut vec![], document.as_table(), &location);
+        sources
+    }
+
+    fn insert_table(
+        &mut self,
+        path: &mut Vec<String>,
+        table: &dyn TableLike,
+        location: &impl Fn(usize) -> Location,
+    ) {
+        for (key, item) in table.iter() {
+            let Some(key_span) = table.key(key).and_then(|key| key.span()) else {
+                continue;
+            };
+            // Tables that are only created by dotted keys have no span of their own
+            let value_span = item.span().unwrap_or(key_span.clone());
+
+            path.push(key.to_owned());
+            self.keys.insert(
+                path.clone(),
+                (location(key_span.start), location(value_span.start)),
+            );
+            if let Some(table) = item.as_table_like() {
+                self.insert_table(path, table, location);
+            }
+            path.pop();
+        }
+    }
+
+    /// Forgets the location of a key and everything in it
+    pub fn remove(&mut self, path: &[String]) {
+        self.keys.retain(|key, _| !key.starts_with(path));
+    }
+
+    /// Adds the locations from a config that is merged over this one
+    pub fn merge(&mut self, overlay: ConfigSources) {
+        self.keys.extend(overlay.keys);
+        self.files.extend(overlay.files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::ConfigSources;
+
+    #[test]
+    fn test_read_sources() {
+        let text = r#"modules = [
+    "fetch_song_info", # [not a table]
+    "rename",
+]
+description = """
+[module.nope]
+key = 1
+"""
+
+[module."rename@backup"]
+template = "%(artist)/%(title).%(ext)"
+logging.level = 'debug'
+"#;
+        let sources = ConfigSources::read(Path::new("test.toml"), text);
+        let position = |location: Option<&super::Location>| {
+            location.map(|location| (location.line, location.column))
+        };
+
+        assert_eq!(Some((1, 11)), position(sources.value(&["modules"])));
+        assert_eq!(None, position(sources.key(&["module", "nope"])));
+        assert_eq!(
+            Some((10, 9)),
+            position(sources.key(&["module", "rename@backup"]))
+        );
+        assert_eq!(
+            Some((11, 12)),
+            position(sources.value(&["module", "rename@backup", "template"]))
+        );
+        assert_eq!(
+            Some((12, 9)),
+            position(sources.key(&["module", "rename@backup", "logging", "level"]))
+        );
+    }
+}
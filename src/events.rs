@@ -0,0 +1,134 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// Something that happened during a run. Written as one JSON object per line with `--events json`
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    StageStarted {
+        stage: usize,
+        modules: Vec<String>,
+    },
+    StageFinished {
+        stage: usize,
+        duration_ms: u64,
+    },
+    ModuleStarted {
+        module: String,
+    },
+    ModuleFinished {
+        module: String,
+        duration_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    DownloadProgress {
+        song: usize,
+        title: String,
+        downloaded_bytes: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total_bytes: Option<u64>,
+    },
+    SongFailed {
+        song: usize,
+        title: String,
+        module: String,
+        error: String,
+    },
+    SongFinished {
+        song: usize,
+        title: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+    },
+    RunFinished {
+        success: bool,
+        cancelled: bool,
+    },
+}
+
+/// Where events are written to. The default sink drops all events
+#[derive(Clone, Default)]
+pub struct EventSink {
+    writer: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl EventSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        EventSink {
+            writer: Some(Arc::new(Mutex::new(writer))),
+        }
+    }
+
+    /// Opens a file or, for targets like `fd:3`, an already open file descriptor
+    pub fn open(target: &str) -> Result<Self> {
+        let path = match target.strip_prefix("fd:") {
+            Some(fd) => {
+                let fd: u32 = fd
+                    .parse()
+                    .with_context(|| format!("Invalid file descriptor '{fd}'"))?;
+                PathBuf::from(format!("/dev/fd/{fd}"))
+            }
+            None => PathBuf::from(target),
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {} for events", path.display()))?;
+        Ok(Self::new(Box::new(file)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn emit(&self, event: Event) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+
+        let line = serde_json::to_string(&event).expect("Events can always be serialized");
+        let mut writer = writer.lock().unwrap();
+        if let Err(error) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+            log::warn!("Failed to write event: {error}");
+        }
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSink")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Event;
+
+    #[test]
+    fn test_event_format() {
+        let event = Event::ModuleFinished {
+            module: String::from("download"),
+            duration_ms: 1500,
+            error: None,
+        };
+        assert_eq!(
+            json!({ "event": "module_finished", "module": "download", "duration_ms": 1500 }),
+            serde_json::to_value(event).unwrap()
+        );
+    }
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{cancel::CancelFlag, cmdline::Args, config::Config, events::EventSink};
+
+/// Data shared by all modules of a run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GlobalContext {
+    pub args: Args,
+    pub config: Config,
+    /// Set when the run should stop. Modules that take long should check it
+    #[serde(skip)]
+    pub cancel: CancelFlag,
+    /// Where modules report their progress to
+    #[serde(skip)]
+    pub events: EventSink,
+}
@@ -0,0 +1,112 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    modules::{Module, ModuleRegistry},
+    validate,
+};
+
+/// Lists every module with its description and dependencies, one module per line
+pub fn module_list(registry: &ModuleRegistry) -> String {
+    let width = registry
+        .modules()
+        .map(|module| module.name().len())
+        .max()
+        .unwrap_or(0);
+
+    let mut list = String::from("Modules:\n");
+    for module in registry.modules() {
+        let _ = write!(
+            list,
+            "    {:<width$}  {}",
+            module.name(),
+            module.description()
+        );
+        if !module.dependencies().is_empty() {
+            let _ = write!(list, " (after {})", module.dependencies().join(", "));
+        }
+        list.push('\n');
+    }
+    list.push_str("\nRun `musicfetch --help <module>` to see the settings of a module\n");
+    list
+}
+
+/// Describes a module with its settings and their defaults
+pub fn module_help(registry: &ModuleRegistry, name: &str) -> Result<String> {
+    let Some(module) = registry.get(name) else {
+        bail!("{}", validate::unknown_module_message(name, registry));
+    };
+    Ok(describe_module(module.as_ref()))
+}
+
+fn describe_module(module: &dyn Module) -> String {
+    let mut help = format!("{} - {}\n", module.name(), module.description());
+
+    let lists = [
+        ("Runs after", module.dependencies()),
+        ("Reads", module.reads()),
+        ("Writes", module.writes()),
+    ];
+    if lists.iter().any(|(_, list)| !list.is_empty()) {
+        help.push('\n');
+    }
+    for (label, list) in lists.iter().filter(|(_, list)| !list.is_empty()) {
+        let _ = writeln!(help, "{label}: {}", list.join(", "));
+    }
+
+    let options = module.config_options();
+    if !options.is_empty() || module.additional_config_values().is_some() {
+        let _ = writeln!(help, "\nSettings in [module.{}]:", module.name());
+    }
+    for option in &options {
+        let _ = write!(help, "    {} ({}", option.key, option.value_type);
+        match &option.default {
+            _ if option.required => help.push_str(", required"),
+            Some(default) => {
+                let _ = write!(help, ", default {default}");
+            }
+            None => {}
+        }
+        let _ = writeln!(help, ")\n        {}", option.description);
+    }
+    if let Some(value_type) = module.additional_config_values() {
+        let _ = writeln!(help, "    Any other key ({value_type})");
+    }
+
+    let variables = module.template_variables();
+    if !variables.is_empty() {
+        help.push_str("\nTemplate variables:\n");
+        let width = variables
+            .iter()
+            .map(|(name, _)| name.len() + 3)
+            .max()
+            .unwrap_or(0);
+        for (name, description) in variables {
+            let _ = writeln!(help, "    {:<width$}  {description}", format!("%({name})"));
+        }
+    }
+
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modules::ModuleRegistry;
+
+    use super::module_help;
+
+    #[test]
+    fn test_module_help() {
+        let registry = ModuleRegistry::with_builtin_modules();
+
+        let help = module_help(&registry, "rename").unwrap();
+        assert!(help.starts_with("rename - "));
+        assert!(help.contains("template (string, default \"%(title).%(ext)\")"));
+        assert!(help.contains("copy (boolean, default false)"));
+        assert!(help.contains("%(track_no)"));
+
+        let error = module_help(&registry, "renam").unwrap_err();
+        assert!(error.to_string().contains("Did you mean 'rename'?"));
+    }
+}
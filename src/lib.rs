@@ -0,0 +1,24 @@
+//! Musicfetch downloads music with yt-dlp and tags it. The work is split into modules that run in
+//! stages. [`Pipeline`] runs them, the `musicfetch` command line is a thin wrapper around it.
+
+pub mod batch;
+pub mod cancel;
+pub mod cmdline;
+pub mod config;
+pub mod config_command;
+pub mod config_sources;
+pub mod events;
+pub mod global;
+pub mod help;
+pub mod local_files;
+pub mod logging;
+pub mod module_util;
+pub mod modules;
+mod pipeline;
+pub mod profiles;
+pub mod schedule;
+pub mod song;
+pub mod state;
+pub mod validate;
+
+pub use pipeline::{Pipeline, PipelineResult};
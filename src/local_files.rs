@@ -0,0 +1,108 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use lofty::{read_from_path, Accessor, TaggedFileExt};
+
+use crate::song::{Song, SongInfo};
+
+/// The extensions of the audio files that are picked up from directories
+const AUDIO_EXTENSIONS: [&str; 13] = [
+    "aac", "aiff", "ape", "flac", "m4a", "mp3", "mp4", "mpc", "ogg", "opus", "spx", "wav", "wv",
+];
+
+/// Lists the audio files in `paths`. Directories are searched recursively and their files are
+/// sorted by path, files that are given directly are used even if their extension is unknown
+pub fn find_audio_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut found = vec![];
+            collect_audio_files(path, &mut found)?;
+            found.sort();
+            files.append(&mut found);
+        } else if path.is_file() {
+            files.push(path.to_owned());
+        } else {
+            bail!("{} is not a file or directory", path.display());
+        }
+    }
+    Ok(files)
+}
+
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_audio_files(&path, files)?;
+        } else if is_audio_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Creates a song from a file on disk with the metadata from its tags
+pub fn read_song(path: &Path) -> Result<Song> {
+    let tagged_file = read_from_path(path)
+        .with_context(|| format!("Failed to read the tags of {}", path.display()))?;
+
+    let mut songinfo = SongInfo {
+        path: Some(path.to_owned()),
+        ..Default::default()
+    };
+    if let Some(tag) = tagged_file.primary_tag().or(tagged_file.first_tag()) {
+        songinfo.title = tag.title().map(|title| title.into_owned());
+        songinfo.album = tag.album().map(|album| album.into_owned());
+        songinfo.artist = tag.artist().map(|artist| artist.into_owned());
+        songinfo.genre = tag.genre().map(|genre| genre.into_owned());
+        songinfo.year = tag.year().map(u64::from);
+        songinfo.track_no = tag.track().map(u64::from);
+        songinfo.total_tracks = tag.track_total().map(u64::from);
+    }
+
+    Ok(Song {
+        songinfo,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::find_audio_files;
+
+    #[test]
+    fn test_find_audio_files() {
+        let dir = env::temp_dir().join(format!("musicfetch-local-test-{}", std::process::id()));
+        let album = dir.join("Album");
+        fs::create_dir_all(&album).unwrap();
+        for file in ["02 b.flac", "01 a.MP3", "cover.jpg"] {
+            fs::write(album.join(file), "").unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let files = find_audio_files(&[dir.clone(), dir.join("notes.txt")]);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            vec![
+                album.join("01 a.MP3"),
+                album.join("02 b.flac"),
+                dir.join("notes.txt")
+            ],
+            files.unwrap()
+        );
+        assert!(find_audio_files(&[dir]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use anyhow::{Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{config::LoggingConfig, modules::module_name_of};
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// While a module shows a UI, log lines for stderr are held back here so they don't end up in the UI
+static STDERR_PAUSED: AtomicBool = AtomicBool::new(false);
+static STDERR_BUFFER: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// Sets up logging from the `[logging]` config section.
+///
+/// `verbosity` is the number of `-v` minus the number of `-q` flags and moves all levels up or down.
+/// Logs go to `log_file` if it is given, the file from the config otherwise and to stderr if there is neither
+pub fn init(config: &LoggingConfig, verbosity: i8, log_file: Option<&Path>) -> Result<()> {
+    let level = parse_level(config.level.as_deref().unwrap_or("info"))?;
+    let level = shift_level(level, verbosity);
+
+    let mut module_levels = BTreeMap::new();
+    for (module, module_level) in &config.modules {
+        module_levels.insert(
+            module.to_owned(),
+            shift_level(parse_level(module_level)?, verbosity),
+        );
+    }
+
+    let output = match log_file.or(config.file.as_deref()) {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            Output::File(Mutex::new(file))
+        }
+        None => Output::Stderr,
+    };
+
+    let max_level = module_levels
+        .values()
+        .copied()
+        .chain([level])
+        .max()
+        .unwrap_or(level);
+
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        module_levels,
+        output,
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(level).with_context(|| {
+        format!("Invalid log level '{level}'. Use off, error, warn, info, debug or trace")
+    })
+}
+
+fn shift_level(level: LevelFilter, verbosity: i8) -> LevelFilter {
+    let index = LEVELS.iter().position(|l| *l == level).unwrap_or(0) as isize;
+    let shifted = (index + verbosity as isize).clamp(0, LEVELS.len() as isize - 1);
+    LEVELS[shifted as usize]
+}
+
+/// Holds back log lines for stderr until [`resume_stderr`] is called. Used while a UI is shown
+pub fn pause_stderr() {
+    STDERR_PAUSED.store(true, Ordering::SeqCst);
+}
+
+/// Writes the log lines that were held back and lets new ones through again
+pub fn resume_stderr() {
+    STDERR_PAUSED.store(false, Ordering::SeqCst);
+
+    let mut buffer = STDERR_BUFFER.lock().unwrap();
+    for line in buffer.drain(..) {
+        eprintln!("{line}");
+    }
+}
+
+enum Output {
+    Stderr,
+    File(Mutex<std::fs::File>),
+}
+
+struct Logger {
+    level: LevelFilter,
+    /// Levels for single modules, by instance or module name
+    module_levels: BTreeMap<String, LevelFilter>,
+    output: Output,
+}
+
+impl Logger {
+    fn level_for(&self, module: Option<&str>) -> LevelFilter {
+        let Some(module) = module else {
+            return self.level;
+        };
+        self.module_levels
+            .get(module)
+            .or_else(|| self.module_levels.get(module_name_of(module)))
+            .copied()
+            .unwrap_or(self.level)
+    }
+}
+
+/// The module the current thread runs. Every module runs in its own thread named after it
+fn current_module() -> Option<String> {
+    thread::current()
+        .name()
+        .filter(|name| *name != "main")
+        .map(|name| name.to_owned())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(current_module().as_deref())
+    }
+
+    fn log(&self, record: &Record) {
+        let module = current_module();
+        if record.level() > self.level_for(module.as_deref()) {
+            return;
+        }
+
+        let line = match module {
+            Some(module) => format!("{:<5} [{}] {}", record.level(), module, record.args()),
+            None => format!("{:<5} {}", record.level(), record.args()),
+        };
+
+        match &self.output {
+            Output::File(file) => {
+                let _ = writeln!(file.lock().unwrap(), "{line}");
+            }
+            Output::Stderr if STDERR_PAUSED.load(Ordering::SeqCst) => {
+                STDERR_BUFFER.lock().unwrap().push(line);
+            }
+            Output::Stderr => eprintln!("{line}"),
+        }
+    }
+
+    fn flush(&self) {
+        if let Output::File(file) = &self.output {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use super::shift_level;
+
+    #[test]
+    fn test_shift_level() {
+        assert_eq!(LevelFilter::Debug, shift_level(LevelFilter::Info, 1));
+        assert_eq!(LevelFilter::Error, shift_level(LevelFilter::Info, -2));
+        assert_eq!(LevelFilter::Off, shift_level(LevelFilter::Info, -10));
+        assert_eq!(LevelFilter::Trace, shift_level(LevelFilter::Warn, 5));
+    }
+}
@@ -1,95 +1,249 @@
-use std::{
-    fs::File,
-    sync::{Arc, Mutex},
-    thread,
-};
-
-use config::get_config;
-use serde_json::{Map, Value};
+use std::{path::PathBuf, process::ExitCode};
 
 use anyhow::{Context, Result};
-use simplelog::{Config, WriteLogger};
+use musicfetch::{
+    batch::{read_batch_file, BatchEntry},
+    cancel::CancelFlag,
+    cmdline::{self, Args},
+    config::{get_config, Config},
+    config_command,
+    events::EventSink,
+    logging,
+    module_util::song_to_string,
+    profiles::Profiles,
+    song::{Song, SongStatus},
+    state::{self, RunState},
+    Pipeline, PipelineResult,
+};
 
-mod cmdline;
-mod config;
-mod module_util;
-mod modules;
+fn main() -> Result<ExitCode> {
+    let args = cmdline::parse_args()?;
 
-// TODO: In the config multible versions of a module could be specified with different configs
-// TODO: Allow stages up to 99 with spaces in between, that way stages can be fit in between the inherited order
+    if let Some(command) = &args.config_command {
+        config_command::run(command, &args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-fn main() -> Result<()> {
-    WriteLogger::init(
-        log::LevelFilter::Info,
-        Config::default(),
-        File::create("/tmp/musiclog")?,
-    )?;
+    // A resumed run continues with the urls and options it was started with, apart from logging and events.
+    // The config is loaded again so fixes made to it since are used
+    let (mut args, resume_state) = match args.resume.clone() {
+        Some(state_file) => {
+            let state = RunState::load(&state_file)?;
+            let args = Args {
+                resume: Some(state_file),
+                // New overrides can fix what made the run fail
+                set: [state.global.args.set.clone(), args.set].concat(),
+                verbosity: args.verbosity,
+                log_file: args.log_file.clone(),
+                events_file: args.events_file.clone(),
+                ..state.global.args.clone()
+            };
+            (args, Some(state))
+        }
+        None => (args, None),
+    };
 
-    let args = cmdline::parse_args()?;
+    if let Some(batch_file) = &args.batch_file {
+        args.batch = read_batch_file(batch_file)?;
+    }
 
     let config_name = args.config.clone().unwrap_or(String::from("default"));
+    let config = load_config(&config_name, &args)?;
+
+    logging::init(&config.logging, args.verbosity, args.log_file.as_deref())
+        .context("Failed to set up logging")?;
+
+    let events = match &args.events_file {
+        Some(events_file) => EventSink::open(events_file)?,
+        None => EventSink::default(),
+    };
+
+    let cancel = CancelFlag::default();
+    let handler_cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            // Pressed a second time, quit without waiting for the modules
+            std::process::exit(130);
+        }
+        eprintln!("\nCancelling... Press Ctrl-C again to quit immediately");
+        handler_cancel.cancel();
+    })
+    .context("Failed to set the Ctrl-C handler")?;
+
+    // A resumed run keeps the configs it was started with
+    let runs = if resume_state.is_none() {
+        plan_runs(config, &args, &cancel)?
+    } else {
+        vec![(config, args)]
+    };
+
+    let several_runs = runs.len() > 1;
+    let mut resume_state = resume_state;
+    let mut exit_code = ExitCode::SUCCESS;
+    for (config, args) in runs {
+        let config_name = args.config.clone().unwrap_or(String::from("default"));
+        if several_runs {
+            log::info!(
+                "Running {} url(s) with the config {config_name}",
+                args.urls.len() + args.batch.len()
+            );
+        }
 
-    let config = get_config(&config_name).context("Failed to load config")?;
-
-    let mut m = Map::new();
-    m.insert(String::from("args"), serde_json::to_value(args)?);
-    m.insert(String::from("config"), config);
-    let global_data = Arc::new(Mutex::new(Value::from(m)));
-    let song_data = Arc::new(Mutex::new(Value::from(Vec::<Value>::new())));
-
-    run_stages(Arc::clone(&global_data), Arc::clone(&song_data));
+        // Dry runs don't change anything, so there is nothing to resume
+        let state_file = match &args.resume {
+            _ if args.dry_run => None,
+            Some(state_file) => Some(state_file.clone()),
+            None if several_runs => Some(PathBuf::from(format!(
+                ".musicfetch-state-{config_name}.json"
+            ))),
+            None => Some(PathBuf::from(state::DEFAULT_STATE_FILE)),
+        };
+
+        let mut pipeline = Pipeline::new(config)
+            .args(args)
+            .events(events.clone())
+            .cancel_with(cancel.clone());
+        if let Some(state) = resume_state.take() {
+            pipeline = pipeline.resume(state);
+        }
+        if let Some(state_file) = &state_file {
+            pipeline = pipeline.state_file(state_file);
+        }
 
-    Ok(())
-}
+        let result = pipeline.run()?;
 
-pub fn run_stages(global_data: Arc<Mutex<Value>>, song_data: Arc<Mutex<Value>>) {
-    let _global = global_data.lock().unwrap();
-    let stages = _global["config"]["stages"].clone();
-    drop(_global);
+        if result.cancelled {
+            eprintln!("Cancelled");
+        } else {
+            report_failures(&result);
+        }
 
-    let mut modules_ran: Vec<String> = vec![];
+        if result.success() {
+            continue;
+        }
 
-    let mut i = 1;
-    while let Some(stage) = stages.get(format!("stage{}", i)) {
-        eprintln!("Running stage {}...", i);
+        if let Some(state_file) = state_file.filter(|state_file| state_file.exists()) {
+            eprintln!(
+                "The progress was saved. Run `musicfetch --resume {}` to try the failed stages again",
+                state_file.display()
+            );
+        }
 
-        let mut handles = vec![];
+        if result.cancelled {
+            return Ok(ExitCode::from(130));
+        }
+        exit_code = ExitCode::FAILURE;
+    }
+    Ok(exit_code)
+}
 
-        let stage_module_names = stage
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|v| v.as_str().unwrap().to_owned());
+/// Loads a config and applies the `--set` overrides to it
+fn load_config(name: &str, args: &Args) -> Result<Config> {
+    let mut config = get_config(name).context("Failed to load config")?;
+    config.apply_overrides(&args.set)?;
 
-        for module_name in stage_module_names.clone() {
-            let module = modules::get_module(&module_name).unwrap();
+    if args.debug_config {
+        config_command::print_config_layers(&config, &args.set);
+    }
+    Ok(config)
+}
 
-            for dependency in module.deps {
-                if !modules_ran.contains(&dependency) {
-                    panic!("Module {module_name} depends on {dependency} but it hasn't run. Please move {dependency} to an earlier stage.");
-                }
+/// Splits the urls into runs by the config they use. A config given in the batch file comes first,
+/// then the `[profiles]` of `config` if no config was chosen with -C. Other urls use `config`
+fn plan_runs(config: Config, args: &Args, cancel: &CancelFlag) -> Result<Vec<(Config, Args)>> {
+    let profiles = match args.config {
+        None => Profiles::new(&config)?,
+        Some(_) => Profiles::default(),
+    };
+
+    let entries = args
+        .urls
+        .iter()
+        .map(|url| BatchEntry::from_url(url))
+        .chain(args.batch.iter().cloned());
+    let mut groups: Vec<(Option<String>, Args)> = vec![];
+    for (i, entry) in entries.enumerate() {
+        cancel.check()?;
+        let config_name = match &entry.config {
+            Some(name) => Some(name.clone()),
+            None => profiles.config_for(&entry.url)?.map(str::to_owned),
+        };
+
+        let group = match groups.iter().position(|(name, _)| *name == config_name) {
+            Some(position) => &mut groups[position].1,
+            None => {
+                let group_args = Args {
+                    config: config_name.clone().or(args.config.clone()),
+                    urls: vec![],
+                    files: vec![],
+                    batch: vec![],
+                    ..args.clone()
+                };
+                groups.push((config_name, group_args));
+                &mut groups.last_mut().unwrap().1
             }
+        };
+        if i < args.urls.len() {
+            group.urls.push(entry.url);
+        } else {
+            group.batch.push(entry);
+        }
+    }
 
-            let _global = Arc::clone(&global_data);
-            let _songs = Arc::clone(&song_data);
+    if groups.is_empty() {
+        return Ok(vec![(config, args.clone())]);
+    }
 
-            let handle = thread::spawn(move || {
-                (module.run_function)(_global, _songs)
-                    .expect(&format!("Error in module {module_name}"));
-            });
-            handles.push(handle);
+    // Local files have no url to choose a config by, they use `config`
+    if !args.files.is_empty() {
+        match groups.iter_mut().find(|(name, _)| name.is_none()) {
+            Some((_, group)) => group.files = args.files.clone(),
+            None => groups.push((
+                None,
+                Args {
+                    urls: vec![],
+                    batch: vec![],
+                    ..args.clone()
+                },
+            )),
         }
+    }
 
-        for handle in handles {
-            handle.join().unwrap();
-        }
+    let mut runs = vec![];
+    let mut default_config = Some(config);
+    for (config_name, group_args) in groups {
+        let group_config = match &config_name {
+            Some(name) => load_config(name, args)
+                .with_context(|| format!("Failed to load the config {name}"))?,
+            None => default_config
+                .take()
+                .expect("Urls without a config of their own are put into one group"),
+        };
+        runs.push((group_config, group_args));
+    }
+    Ok(runs)
+}
 
-        modules_ran.append(
-            &mut stage_module_names
-                .map(String::from)
-                .collect::<Vec<String>>(),
-        );
+/// Prints the modules and songs that failed
+fn report_failures(result: &PipelineResult) {
+    let PipelineResult {
+        songs,
+        failed_modules,
+        ..
+    } = result;
 
-        i += 1;
+    if failed_modules.is_empty() && songs.iter().all(Song::is_ok) {
+        return;
+    }
+
+    eprintln!();
+    eprintln!("Finished with errors:");
+    for (module_name, error) in failed_modules {
+        eprintln!("  Module {module_name} failed: {error:#}");
+    }
+    for song in songs {
+        if let SongStatus::Failed { module, error } = &song.status {
+            eprintln!("  '{}' failed in {module}: {error}", song_to_string(song))
+        }
     }
 }
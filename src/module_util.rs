@@ -1,25 +1,135 @@
-use serde_json::Value;
-use anyhow::{Result, anyhow};
+use std::path::Path;
 
+use anyhow::{bail, Context, Error, Result};
+use serde_json::{Map, Value};
 
-pub fn song_to_string(song: &Value) -> String {
-    match song["songinfo"].get("title") {
-        Some(song_name) => song_name,
-        None => &song["yt_dlp"]["title"],
+use crate::{
+    global::GlobalContext,
+    song::{Song, SongStatus},
+};
+
+pub fn song_to_string(song: &Song) -> String {
+    match &song.songinfo.title {
+        Some(title) => title.to_owned(),
+        None => song.yt_dlp["title"]
+            .as_str()
+            .or_else(|| song.yt_dlp["webpage_url"].as_str())
+            .map(str::to_owned)
+            .or_else(|| {
+                // Local files have no yt-dlp json
+                let path = song.songinfo.path.as_ref()?;
+                Some(path.display().to_string())
+            })
+            .unwrap_or_default(),
     }
-    .as_str()
-    .expect("Failed creating string from song")
-    .to_owned()
 }
 
-pub fn get_songinfo_field<'a,T: ?Sized>(song: &'a Value, field: &str) -> Result<&'a T> 
-    where &'a Value: TryInto<&'a T> 
+pub fn get_song_path(song: &Song) -> Result<&Path> {
+    song.songinfo
+        .path
+        .as_deref()
+        .with_context(|| format!("Song '{}' has no path", song_to_string(song)))
+}
+
+/// Marks a song as failed so that later modules skip it
+pub fn mark_failed(song: &mut Song, module_name: &str, error: Error) {
+    log::error!(
+        "Error in module {module_name} for song '{}': {error:#}",
+        song_to_string(song)
+    );
+
+    song.status = SongStatus::Failed {
+        module: module_name.to_owned(),
+        error: format!("{error:#}"),
+    };
+}
+
+/// Runs `f` for every song that hasn't failed. If `f` returns an error the song is marked as failed
+pub fn for_each_ok_song<F>(songs: &mut [Song], module_name: &str, mut f: F)
+where
+    F: FnMut(&mut Song) -> Result<()>,
 {
-    match song["songinfo"].get(field) {
-        Some(v) => match v.try_into() {
-            Ok(v) => Ok(v),
-            Err(_) => Result::Err(anyhow!("Error getting song field '{}' for song '{}' field has wrong type", field, song_to_string(song)))
-        },
-        None => Result::Err(anyhow!("Error getting song field '{}' for song '{}' field does not exist", field, song_to_string(song)))
+    for song in songs.iter_mut().filter(|song| song.is_ok()) {
+        if let Err(error) = f(song) {
+            mark_failed(song, module_name, error);
+        }
     }
-}
\ No newline at end of file
+}
+
+/// The `required_fields` setting of a UI module, or `default_required` if it isn't set
+pub fn required_fields(
+    global: &GlobalContext,
+    instance: &str,
+    default_required: &[&str],
+) -> Vec<String> {
+    match global
+        .config
+        .module_config(instance)
+        .and_then(|config| config.get("required_fields"))
+        .and_then(Value::as_array)
+    {
+        Some(fields) => fields
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_owned)
+            .collect(),
+        None => default_required
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+    }
+}
+
+/// Used by UI modules with `--non-interactive`. The songinfo is kept as it is, but songs that are
+/// missing one of the `required` fields fail since there is nobody to ask for them
+pub fn accept_without_ui(instance: &str, required: &[String], songs: &mut [Song]) {
+    for_each_ok_song(songs, instance, |song| {
+        let missing: Vec<&str> = required
+            .iter()
+            .filter(|field| {
+                // The UIs set fields that were left empty to empty strings
+                !song
+                    .songinfo
+                    .get_field(field)
+                    .is_some_and(|value| value != "")
+            })
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "Missing {}. Give them with --album, --artist, --year and --genre or in a batch file, or run without --non-interactive",
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    });
+}
+
+/// Merges changed song data into a song. Objects are merged key by key, other values are replaced.
+/// The status of the song is kept, so the changes can't undo a failure
+pub fn merge_into_song(song: &mut Song, changes: Map<String, Value>) -> Result<()> {
+    let Value::Object(mut song_obj) = serde_json::to_value(&*song)? else {
+        bail!("Song is not an Object, this is an Error.");
+    };
+
+    merge_b_into_a(&mut song_obj, changes);
+
+    let status = song.status.clone();
+    *song = serde_json::from_value(Value::Object(song_obj))?;
+    song.status = status;
+    Ok(())
+}
+
+pub fn merge_b_into_a(a: &mut Map<String, Value>, b: Map<String, Value>) {
+    for (key, b_value) in b.into_iter() {
+        if let Some(mut a_value) = a.get_mut(&key) {
+            if let (Value::Object(a_obj), Value::Object(b_obj)) = (&mut a_value, &b_value) {
+                merge_b_into_a(a_obj, b_obj.clone());
+            } else {
+                *a_value = b_value;
+            }
+        } else {
+            a.insert(key, b_value);
+        }
+    }
+}
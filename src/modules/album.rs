@@ -1,20 +1,73 @@
 use std::sync::{Arc, Mutex};
 
-use anyhow::{bail, Ok};
+use anyhow::{Context, Ok};
 use cursive::{
     theme::Theme,
     view::{Nameable, Resizable},
     views::{Dialog, EditView, LinearLayout, TextView},
     Cursive, CursiveExt,
 };
+
 use serde_json::Value;
 
 use crate::{
-    define_module,
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    module_util::{accept_without_ui, required_fields},
+    modules::{self, ConfigOption, Module, Resource, ValueType},
+    song::Song,
 };
 
-define_module!("albumui", run, [modules::jsonfetch::MODULE_NAME]);
+pub const MODULE_NAME: &str = "albumui";
+
+/// Fields that have to be set with `--non-interactive` if the config doesn't list others
+const REQUIRED_FIELDS: [&str; 2] = ["album", "artist"];
+
+pub struct AlbumUi;
+
+impl Module for AlbumUi {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Opens a dialog for entering the album title, artist, year and genre of all songs at once. With --non-interactive the values are kept as they are"
+    }
+
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![ConfigOption {
+            key: "required_fields",
+            value_type: ValueType::StringArray,
+            default: Some(Value::from(REQUIRED_FIELDS.to_vec())),
+            required: false,
+            description: "Fields that have to be set when running with --non-interactive. Songs missing one of them fail",
+        }]
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &["album", "artist", "year", "genre"]
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &["album", "artist", "year", "genre"]
+    }
+
+    fn locks(&self) -> &'static [Resource] {
+        &[Resource::Terminal]
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> anyhow::Result<()> {
+        run(instance, global, songs)
+    }
+}
 
 #[derive(Default)]
 struct AlbumMetadata {
@@ -24,57 +77,59 @@ struct AlbumMetadata {
     genre: String,
 }
 
-macro_rules! get_value_if_exists {
-    ($metadata_key:expr, $key:expr, $song:ident) => {
-        if let Value::String(value) = &$song["songinfo"][$key] {
-            $metadata_key = value.to_owned();
-        }
-    };
-
-    ($metadata_key:expr, $key:expr, $song:ident, $conversion:ident) => {
-        if let Value::Number(value) = &$song["songinfo"][$key] {
-            let Some(value) = value.$conversion() else {
-                bail!(
-                    "Error in songinfo for {}: {} value is not a unsigned integer",
-                    $song["yt_dlp"][$key],
-                    $key
-                );
-            };
-            $metadata_key = Some(value as u64);
-        }
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> anyhow::Result<()> {
+    let required = {
+        let global = global.lock().unwrap();
+        global
+            .args
+            .non_interactive
+            .then(|| required_fields(&global, instance, &REQUIRED_FIELDS))
     };
-}
+    if let Some(required) = required {
+        accept_without_ui(instance, &required, &mut songs.lock().unwrap());
+        return Ok(());
+    }
 
-fn run(_global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> anyhow::Result<()> {
     let mut album = AlbumMetadata::default();
     {
         let songs = songs.lock().unwrap();
-        let songs = songs.as_array().unwrap();
 
-        for song in songs {
-            get_value_if_exists!(album.title, "album", song);
-            get_value_if_exists!(album.artist, "artist", song);
-            get_value_if_exists!(album.year, "year", song, as_u64);
-            get_value_if_exists!(album.genre, "genre", song);
+        for song in songs.iter() {
+            let songinfo = &song.songinfo;
+            if let Some(title) = &songinfo.album {
+                album.title = title.to_owned();
+            }
+            if let Some(artist) = &songinfo.artist {
+                album.artist = artist.to_owned();
+            }
+            if songinfo.year.is_some() {
+                album.year = songinfo.year;
+            }
+            if let Some(genre) = &songinfo.genre {
+                album.genre = genre.to_owned();
+            }
         }
     }
 
-    let metadata = show_album_metadata_ui(album);
+    let metadata = show_album_metadata_ui(album)?;
 
     let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
 
-    for song in songs {
-        song["songinfo"]["album"] = Value::from(metadata.title.clone());
-        song["songinfo"]["artist"] = Value::from(metadata.artist.clone());
-        song["songinfo"]["year"] = Value::from(metadata.year);
-        song["songinfo"]["genre"] = Value::from(metadata.genre.clone());
+    for song in songs.iter_mut() {
+        song.songinfo.album = Some(metadata.title.clone());
+        song.songinfo.artist = Some(metadata.artist.clone());
+        song.songinfo.year = metadata.year;
+        song.songinfo.genre = Some(metadata.genre.clone());
     }
 
     Ok(())
 }
 
-fn show_album_metadata_ui(album: AlbumMetadata) -> AlbumMetadata {
+fn show_album_metadata_ui(album: AlbumMetadata) -> anyhow::Result<AlbumMetadata> {
     let mut siv = Cursive::default();
 
     siv.set_theme(Theme::terminal_default());
@@ -109,10 +164,11 @@ fn show_album_metadata_ui(album: AlbumMetadata) -> AlbumMetadata {
 
     siv.add_layer(dialog);
 
-    siv.run_crossterm()
-        .expect("TUI initialization failed. Try using another Terminal");
+    siv.run_crossterm().context(
+        "TUI initialization failed. Try using another Terminal or run with --non-interactive",
+    )?;
 
-    siv.take_user_data().unwrap()
+    Ok(siv.take_user_data().unwrap())
 }
 
 fn get_album_metadata_layout(album: AlbumMetadata) -> LinearLayout {
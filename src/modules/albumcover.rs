@@ -1,52 +1,115 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use lofty::{MimeType, Picture, PictureType, TagExt};
-use serde_json::Value;
 
 use crate::{
-    define_module,
-    modules::{tag_files::get_song_tag, ModuleStruct},
+    global::GlobalContext,
+    module_util::{for_each_ok_song, get_song_path},
+    modules::{self, tag_files::get_song_tag, Module, Resource},
+    song::Song,
 };
 
-define_module!("albumcover", run, []);
+pub const MODULE_NAME: &str = "albumcover";
 
-fn run(global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
-    let Some(cover_url) = get_cover_url(global) else {
-        // No cover url was given, return
-        return Ok(());
-    };
+pub struct AlbumCover;
 
-    let resp = minreq::get(cover_url)
-        .send()
-        .expect("Sending http request for cover image failed");
-    let kind = infer::get(resp.as_bytes()).expect("Filetype for cover not found");
+impl Module for AlbumCover {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
 
-    let picture = Picture::new_unchecked(
-        PictureType::CoverFront,
-        MimeType::from_str(kind.mime_type()),
-        None,
-        resp.into_bytes(),
-    );
+    fn description(&self) -> &'static str {
+        "Downloads the cover given with --cover_url, or the cover_url of a batch file entry, and embeds it into the songs"
+    }
 
-    let songs = songs.lock().unwrap();
-    let songs = songs.as_array().unwrap();
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::download::MODULE_NAME]
+    }
 
-    for song in songs {
-        let mut tag = get_song_tag(song)?;
+    fn reads(&self) -> &'static [&'static str] {
+        &["path", "cover_url"]
+    }
 
-        tag.push_picture(picture.clone());
+    fn locks(&self) -> &'static [Resource] {
+        &[Resource::AudioFiles]
+    }
 
-        tag.save_to_path(song["songinfo"]["path"].as_str().unwrap())?;
+    fn run(
+        &self,
+        _instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(global, songs)
     }
+}
+
+fn run(global: Arc<Mutex<GlobalContext>>, songs: Arc<Mutex<Vec<Song>>>) -> Result<()> {
+    let global = global.lock().unwrap();
+    let dry_run = global.args.dry_run;
+    let default_cover_url = global.args.cover_url.clone();
+    drop(global);
+
+    // Covers are downloaded once and shared by all songs with the same url. A failed download is
+    // kept as well so it is not tried again for every song
+    let mut pictures: HashMap<String, Result<Picture, String>> = HashMap::new();
+    let mut songs = songs.lock().unwrap();
+
+    for_each_ok_song(&mut songs, MODULE_NAME, |song| {
+        let Some(cover_url) = get_cover_url(song).or_else(|| default_cover_url.clone()) else {
+            // No cover url was given for this song
+            return Ok(());
+        };
+
+        if dry_run {
+            log::info!(
+                "Would embed the cover from {cover_url} into '{}'",
+                get_song_path(song)?.display()
+            );
+            return Ok(());
+        }
+
+        let picture = pictures
+            .entry(cover_url.clone())
+            .or_insert_with(|| download_cover(&cover_url).map_err(|error| format!("{error:#}")))
+            .clone()
+            .map_err(|error| anyhow!(error))?;
+
+        let mut tag = get_song_tag(song)?;
+
+        // Replace the cover instead of adding a second one if the song already has one
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(picture);
+
+        tag.save_to_path(get_song_path(song)?)?;
+        Ok(())
+    });
 
     Ok(())
 }
 
-fn get_cover_url(global: Arc<Mutex<Value>>) -> Option<String> {
-    let global = global.lock().unwrap();
-    let global = global.as_object().unwrap();
+fn download_cover(cover_url: &str) -> Result<Picture> {
+    let resp = minreq::get(cover_url)
+        .send()
+        .context("Sending http request for cover image failed")?;
+    let kind = infer::get(resp.as_bytes()).context("Filetype for cover not found")?;
 
-    global["args"]["cover_url"].as_str().map(|c| c.to_owned())
+    Ok(Picture::new_unchecked(
+        PictureType::CoverFront,
+        MimeType::from_str(kind.mime_type()),
+        None,
+        resp.into_bytes(),
+    ))
 }
 
+/// The cover url a song brings along from the batch file
+fn get_cover_url(song: &Song) -> Option<String> {
+    song.songinfo
+        .get_field("cover_url")?
+        .as_str()
+        .map(str::to_owned)
+}
@@ -1,95 +1,293 @@
 use std::{
-    io::{Read, Write},
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
     process::{Command, Stdio},
     sync::{Arc, Mutex},
+    thread,
 };
 
-use anyhow::{Error, Result};
+use anyhow::{bail, Result};
 use serde_json::Value;
 
 use crate::{
-    define_module,
-    modules::{self, ModuleStruct},
+    cancel::{CancelFlag, Cancelled},
+    events::{Event, EventSink},
+    global::GlobalContext,
+    module_util::{mark_failed, song_to_string},
+    modules::{self, ConfigOption, Module, ValueType},
+    song::Song,
 };
 
-define_module!("download", run, [modules::jsonfetch::MODULE_NAME]);
+pub const MODULE_NAME: &str = "download";
 
-const YT_DLP_ARGS: [&str; 4] = ["--ignore-config", "-x", "-o", "%(id)s.%(ext)s"];
+pub struct Download;
+
+impl Module for Download {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Downloads the audio of every song using yt-dlp"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
 
-fn run(global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
-    let song_json_list;
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![ConfigOption {
+            key: "yt_dlp_args",
+            value_type: ValueType::StringArray,
+            default: Some(Value::Array(vec![])),
+            required: false,
+            description: "Extra arguments to give to yt-dlp. For example ['--audio-format', 'mp3'] if you want to download everything as mp3",
+        }]
+    }
 
-    let module_config;
-    {
-        let mut _songs = songs.lock().unwrap();
-        song_json_list = _songs.as_array().unwrap().clone();
+    fn writes(&self) -> &'static [&'static str] {
+        &["path"]
+    }
 
-        let mut _global = global.lock().unwrap();
-        module_config = _global
-            .pointer(&format!("/config/module/{}", MODULE_NAME))
-            .map(|v| v.to_owned());
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
     }
+}
+
+const YT_DLP_ARGS: [&str; 4] = ["--ignore-config", "-x", "-o", "%(id)s.%(ext)s"];
 
-    let args = get_yt_dlp_args(module_config);
+/// Marks the lines yt-dlp prints with the progress of a download
+const PROGRESS_PREFIX: &str = "musicfetch-progress ";
+const PROGRESS_TEMPLATE: &str = "download:musicfetch-progress %(progress.downloaded_bytes)s %(progress.total_bytes,progress.total_bytes_estimate)s";
 
-    let mut filenames = vec![];
-    for song_json in song_json_list {
-        let yt_dlp_json = song_json["yt_dlp"].to_string();
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
+    let song_list = songs.lock().unwrap().clone();
 
-        download(&yt_dlp_json, &args)?;
+    let global = global.lock().unwrap();
+    let module_config = global.config.module_config(instance).cloned();
+    let dry_run = global.args.dry_run;
+    let cancel = global.cancel.clone();
+    let events = global.events.clone();
+    drop(global);
 
-        filenames.push(get_downloaded_filename(&yt_dlp_json, &args)?);
+    let args = get_yt_dlp_args(instance, module_config)?;
+
+    let mut results = vec![];
+    for (i, song) in song_list.into_iter().enumerate() {
+        // Songs that already have a file were downloaded before a resumed run
+        let already_downloaded = matches!(&song.songinfo.path, Some(path) if path.exists());
+        if !song.is_ok() || already_downloaded {
+            results.push(None);
+            continue;
+        }
+
+        let progress = events.is_enabled().then(|| ProgressReporter {
+            events: events.clone(),
+            song: i,
+            title: song_to_string(&song),
+        });
+        let result = download_song(&song, &args, dry_run, &cancel, progress);
+        if matches!(&result, Err(error) if error.is::<Cancelled>()) {
+            remove_partial_files(&song);
+            break;
+        }
+        results.push(Some(result));
     }
 
     let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
-    for (song, filename) in songs.iter_mut().zip(filenames) {
-        song["songinfo"]["path"] = Value::from(filename);
+    for (song, result) in songs.iter_mut().zip(results) {
+        match result {
+            Some(Ok(filename)) => song.songinfo.path = Some(PathBuf::from(filename)),
+            Some(Err(error)) => mark_failed(song, instance, error),
+            None => {}
+        }
+    }
+
+    // The songs that finished downloading keep their path so a resumed run can use them
+    cancel.check()
+}
+
+/// Downloads a song and returns the path of the downloaded file.
+/// In a dry run only the command is logged and the path the file would have is returned
+fn download_song(
+    song: &Song,
+    args: &[String],
+    dry_run: bool,
+    cancel: &CancelFlag,
+    progress: Option<ProgressReporter>,
+) -> Result<String> {
+    let yt_dlp_json = song.yt_dlp.to_string();
+
+    if dry_run {
+        log::info!(
+            "Would download '{}' with: yt-dlp {} --load-info-json -",
+            song_to_string(song),
+            args.iter()
+                .map(|arg| quote_arg(arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    } else {
+        download(&yt_dlp_json, args, cancel, progress)?;
+    }
+
+    get_downloaded_filename(&yt_dlp_json, args)
+}
+
+/// Quotes an argument so the printed command can be pasted into a shell
+fn quote_arg(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@".contains(c));
+    if is_plain {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
     }
-    Ok(())
 }
 
-fn download(yt_dlp_json: &str, args: &Vec<String>) -> Result<()> {
-    let mut download_process = Command::new("yt-dlp")
+fn download(
+    yt_dlp_json: &str,
+    args: &[String],
+    cancel: &CancelFlag,
+    progress: Option<ProgressReporter>,
+) -> Result<()> {
+    let mut command = Command::new("yt-dlp");
+    command
         .args(args)
         .arg("--load-info-json")
         .arg("-")
         .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .spawn()?;
+        .stdout(Stdio::null());
+    if progress.is_some() {
+        command
+            .args([
+                "--progress",
+                "--newline",
+                "--progress-template",
+                PROGRESS_TEMPLATE,
+            ])
+            .stdout(Stdio::piped());
+    }
+    let mut download_process = command.spawn()?;
 
-    let stdin = download_process
+    let reader = progress.map(|progress| {
+        let stdout = download_process
+            .stdout
+            .take()
+            .expect("Failed to capture yt-dlp stdout");
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                progress.report(&line);
+            }
+        })
+    });
+
+    let mut stdin = download_process
         .stdin
-        .as_mut()
+        .take()
         .expect("Failed to write to yt-dlp stdin");
     // If it errors with broken pipe error on this line
     // it's because you piped the stdout into another process and that process crashed
     stdin.write_all(yt_dlp_json.as_bytes())?;
-    if !download_process.wait()?.success() {
-        return Err(Error::msg("something went wrong with yt-dlp"));
+    // yt-dlp reads until the end of its input
+    drop(stdin);
+
+    let status = cancel.wait_for(&mut download_process)?;
+    if let Some(reader) = reader {
+        let _ = reader.join();
+    }
+    if !status.success() {
+        // yt-dlp also gets Ctrl-C and exits on its own before we kill it
+        cancel.check()?;
+        bail!("something went wrong with yt-dlp");
     }
     Ok(())
 }
 
-fn get_yt_dlp_args(module_config: Option<Value>) -> Vec<String> {
-    let mut args = YT_DLP_ARGS.map(|s| s.to_owned()).to_vec();
-    let mut extra_args =
-        match module_config.and_then(|v| v["yt_dlp_args"].as_array().map(|v| v.to_owned())) {
-            Some(v) => v
-                .into_iter()
-                .map(|v| {
-                    v.as_str()
-                        .expect("Download module yt_dlp_json config is not an array of strings")
-                        .to_owned()
-                })
-                .collect(),
-            None => vec![],
+/// Turns the progress lines yt-dlp prints into events
+struct ProgressReporter {
+    events: EventSink,
+    song: usize,
+    title: String,
+}
+
+impl ProgressReporter {
+    fn report(&self, line: &str) {
+        let Some(progress) = line.strip_prefix(PROGRESS_PREFIX) else {
+            return;
+        };
+        // yt-dlp prints NA for numbers it doesn't know and estimates as floats
+        let mut numbers = progress
+            .split_whitespace()
+            .map(|number| number.parse::<f64>().ok().map(|number| number as u64));
+        let Some(Some(downloaded_bytes)) = numbers.next() else {
+            return;
         };
+
+        self.events.emit(Event::DownloadProgress {
+            song: self.song,
+            title: self.title.clone(),
+            downloaded_bytes,
+            total_bytes: numbers.next().flatten(),
+        });
+    }
+}
+
+/// Deletes the files an interrupted yt-dlp leaves behind for a song
+fn remove_partial_files(song: &Song) {
+    let Some(id) = song.yt_dlp["id"].as_str() else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(".") else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let is_partial = file_name.ends_with(".part")
+            || file_name.contains(".part-Frag")
+            || file_name.ends_with(".ytdl")
+            || file_name.contains(".temp.");
+
+        if file_name.starts_with(&format!("{id}.")) && is_partial {
+            log::info!("Removing partial download {}", file_name);
+            if let Err(error) = fs::remove_file(entry.path()) {
+                log::warn!("Failed to remove {}: {}", file_name, error);
+            }
+        }
+    }
+}
+
+fn get_yt_dlp_args(instance: &str, module_config: Option<Value>) -> Result<Vec<String>> {
+    let mut args = YT_DLP_ARGS.map(|s| s.to_owned()).to_vec();
+    let extra_args = match module_config.as_ref().and_then(|v| v.get("yt_dlp_args")) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|v| v.as_str().map(str::to_owned))
+            .collect::<Option<Vec<String>>>(),
+        Some(_) => None,
+        None => Some(vec![]),
+    };
+    let Some(mut extra_args) = extra_args else {
+        bail!("module.{instance}.yt_dlp_args has to be an array of strings");
+    };
     args.append(&mut extra_args);
-    args
+    Ok(args)
 }
 
-fn get_downloaded_filename(yt_dlp_json: &str, args: &Vec<String>) -> Result<String> {
+fn get_downloaded_filename(yt_dlp_json: &str, args: &[String]) -> Result<String> {
     let mut filename = String::new();
 
     let mut filename_process = Command::new("yt-dlp")
@@ -109,8 +307,11 @@ fn get_downloaded_filename(yt_dlp_json: &str, args: &Vec<String>) -> Result<Stri
         .stdout
         .take()
         .expect("Failed to capture yt-dlp stdout");
-    filename_process.wait()?;
+    let status = filename_process.wait()?;
     stdout.read_to_string(&mut filename)?;
+    if !status.success() {
+        bail!("yt-dlp failed to print the path of the downloaded file");
+    }
 
-    return Ok(filename.trim().to_owned());
+    Ok(filename.trim().to_owned())
 }
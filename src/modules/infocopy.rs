@@ -1,43 +1,87 @@
 use std::sync::{Arc, Mutex};
 
-use serde_json::Value;
-
-use anyhow::{bail, Ok, Result};
+use anyhow::{bail, Context, Ok, Result};
 
 use crate::{
-    define_module,
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    module_util::for_each_ok_song,
+    modules::{self, Module, ValueType, SONGINFO_FIELDS},
+    song::Song,
 };
 
-define_module!("infocopy", run, [modules::jsonfetch::MODULE_NAME]);
+pub const MODULE_NAME: &str = "infocopy";
+
+pub struct InfoCopy;
+
+impl Module for InfoCopy {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Copies values from the yt-dlp json to the songinfo. Each setting maps a songinfo field to a yt-dlp field. Metadata from the batch file or command line is merged over it afterwards"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn additional_config_values(&self) -> Option<ValueType> {
+        Some(ValueType::String)
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
+    }
+}
 
 // TODO: Allow using regex with captures to copy only parts of strings
-fn run(global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
     let global = global.lock().unwrap();
-    let infocopy_settings = global["config"]["module"]
-        .get("infocopy")
-        .expect("Module infocopy has no settings")
-        .as_object()
-        .expect("Infocopy settings is not a object")
-        .clone();
+    let infocopy_settings = global
+        .config
+        .module_config(instance)
+        .and_then(|settings| settings.as_object())
+        .cloned()
+        .unwrap_or_default();
     drop(global);
 
-    let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
+    let mut mappings = vec![];
+    for (key, value) in infocopy_settings {
+        let Some(yt_dlp_key) = value.as_str() else {
+            bail!("Map key for {} is not a string", key)
+        };
+        mappings.push((key, yt_dlp_key.to_owned()));
+    }
 
-    for song in songs {
-        for (key, value) in &infocopy_settings {
-            let Some(yt_dlp_key) = value.as_str() else {
-                bail!("Map key for {} is not a string", key)
-            };
+    let mut songs = songs.lock().unwrap();
 
-            let Some(yt_dlp_value) = song["yt_dlp"].get(yt_dlp_key) else {
+    for_each_ok_song(&mut songs, instance, |song| {
+        for (key, yt_dlp_key) in &mappings {
+            let Some(yt_dlp_value) = song.yt_dlp.get(yt_dlp_key).cloned() else {
                 continue;
             };
 
-            song["songinfo"][key] = yt_dlp_value.clone();
+            song.songinfo
+                .set_field(key, yt_dlp_value)
+                .with_context(|| format!("Failed copying yt-dlp field '{}'", yt_dlp_key))?;
         }
-    }
+        // Metadata given ahead of time wins over the yt-dlp json
+        song.songinfo.merge(&song.preset)
+    });
 
     Ok(())
 }
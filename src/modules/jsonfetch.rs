@@ -1,43 +1,166 @@
 use std::{
-    process::{Command, Stdio},
+    io::Read,
+    process::{Command, ExitStatus, Stdio},
     sync::{Arc, Mutex},
+    thread,
 };
 
 use anyhow::{Context, Ok, Result};
-use serde_json::{Map, Value};
+use serde_json::json;
 
-use crate::{define_module, modules::ModuleStruct};
+use crate::{
+    batch::BatchEntry,
+    cancel::CancelFlag,
+    cmdline::Args,
+    global::GlobalContext,
+    local_files,
+    module_util::mark_failed,
+    modules::Module,
+    song::{Song, SongInfo, SongStatus},
+};
+
+pub const MODULE_NAME: &str = "fetch_song_info";
+
+pub struct JsonFetch;
+
+impl Module for JsonFetch {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Fetches the yt-dlp info json for every url given on the command line or in the batch file, and reads the tags of the local files given with --local"
+    }
 
-define_module!("fetch_song_info", run, []);
+    fn run(
+        &self,
+        _instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(global, songs)
+    }
+}
 
 // TODO: Implement caching
-fn run(global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
-    let mut global = global.lock().unwrap();
-    let global = global.as_object_mut().unwrap();
+fn run(global: Arc<Mutex<GlobalContext>>, songs: Arc<Mutex<Vec<Song>>>) -> Result<()> {
+    let global = global.lock().unwrap();
+    let entries: Vec<BatchEntry> = global
+        .args
+        .urls
+        .iter()
+        .map(|url| BatchEntry::from_url(url))
+        .chain(global.args.batch.iter().cloned())
+        .collect();
+    let args = global.args.clone();
+    let cancel = global.cancel.clone();
+    drop(global);
 
-    for url in global["args"]["urls"].as_array().unwrap() {
-        let json_output = Command::new("yt-dlp")
-            .arg("-j")
-            .arg(url.as_str().unwrap())
-            .stderr(Stdio::inherit())
-            .output()
-            .context("Running yt-dlp command failed. Check if it is installed")?;
+    // Local files are read before any url is fetched
+    for path in local_files::find_audio_files(&args.files)? {
+        cancel.check()?;
+        let song = local_files::read_song(&path)
+            .and_then(|mut song| {
+                // The album fields from the command line replace the ones from the tags
+                song.preset = album_fields(&args);
+                song.songinfo.merge(&song.preset)?;
+                Ok(song)
+            })
+            .unwrap_or_else(|error| {
+                let mut song = Song {
+                    songinfo: SongInfo {
+                        path: Some(path),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                mark_failed(&mut song, MODULE_NAME, error);
+                song
+            });
+        songs.lock().unwrap().push(song);
+    }
 
-        let json_output =
-            String::from_utf8(json_output.stdout).context("Parsing yt-dlp output failed.")?;
+    for BatchEntry {
+        url, mut songinfo, ..
+    } in entries
+    {
+        fill_album_fields(&mut songinfo, &args);
+        let (status, json_output) = fetch_url(&url, &cancel)?;
 
         let mut songs = songs.lock().unwrap();
-        let songs = songs.as_array_mut().unwrap();
         for line in json_output.lines() {
-            let mut m = Map::new();
-            m.insert(
-                String::from("yt_dlp"),
-                serde_json::from_str(line)
-                    .with_context(|| format!("yt-dlp outputted invalid JSON: \n {}", line))?,
-            );
-            m.insert(String::from("songinfo"), Map::new().into());
-            songs.push(m.into());
+            let mut song = Song::from_yt_dlp_json(line)?;
+            // The metadata from the batch file and command line is there before any other module
+            // runs. Infocopy merges it again after copying the yt-dlp fields
+            song.songinfo = songinfo.clone();
+            song.preset = songinfo.clone();
+            songs.push(song);
+        }
+
+        if !status.success() {
+            let error = if json_output.trim().is_empty() {
+                format!("yt-dlp could not fetch {url}")
+            } else {
+                format!("yt-dlp could not fetch some of the songs in {url}")
+            };
+            log::error!("{error}");
+
+            songs.push(Song {
+                yt_dlp: json!({ "webpage_url": url }),
+                songinfo: songinfo.clone(),
+                preset: songinfo,
+                status: SongStatus::Failed {
+                    module: MODULE_NAME.to_owned(),
+                    error,
+                },
+            });
         }
     }
     Ok(())
 }
+
+/// Sets the album fields given on the command line that the batch file entry doesn't set
+fn fill_album_fields(songinfo: &mut SongInfo, args: &Args) {
+    songinfo.album = songinfo.album.take().or_else(|| args.album.clone());
+    songinfo.artist = songinfo.artist.take().or_else(|| args.artist.clone());
+    songinfo.year = songinfo.year.or(args.year);
+    songinfo.genre = songinfo.genre.take().or_else(|| args.genre.clone());
+}
+
+/// The album fields given on the command line
+fn album_fields(args: &Args) -> SongInfo {
+    SongInfo {
+        album: args.album.clone(),
+        artist: args.artist.clone(),
+        year: args.year,
+        genre: args.genre.clone(),
+        ..Default::default()
+    }
+}
+
+/// Runs `yt-dlp -j` for a url and returns its exit status and output
+fn fetch_url(url: &str, cancel: &CancelFlag) -> Result<(ExitStatus, String)> {
+    let mut fetch_process = Command::new("yt-dlp")
+        .arg("-j")
+        .arg(url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Running yt-dlp command failed. Check if it is installed")?;
+
+    // Read in another thread so yt-dlp doesn't block on a full pipe while we wait for it
+    let mut stdout = fetch_process
+        .stdout
+        .take()
+        .expect("Failed to capture yt-dlp stdout");
+    let reader = thread::spawn(move || {
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let status = cancel.wait_for(&mut fetch_process)?;
+
+    let output = reader.join().expect("Reading the yt-dlp output panicked")?;
+    let json_output = String::from_utf8(output).context("Parsing yt-dlp output failed.")?;
+    Ok((status, json_output))
+}
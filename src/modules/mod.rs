@@ -1,53 +1,217 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::{bail, Ok, Result};
+use anyhow::{bail, Result};
 use serde_json::Value;
 
+use crate::{global::GlobalContext, song::Song};
+
 mod album;
 mod albumcover;
 mod download;
 mod infocopy;
 mod jsonfetch;
 mod rename;
+mod rhai_script;
+mod script;
 mod songcounter;
 mod tag_files;
 mod tagui;
 
-type ModuleRunFunction = fn(Arc<Mutex<Value>>, Arc<Mutex<Value>>) -> Result<()>;
+/// The songinfo fields every module knows about
+pub const SONGINFO_FIELDS: [&str; 7] = [
+    "title",
+    "album",
+    "artist",
+    "genre",
+    "year",
+    "track_no",
+    "total_tracks",
+];
+
+pub trait Module: Send + Sync {
+    /// The name used for this module in the config
+    fn name(&self) -> &'static str;
+
+    /// A short human-readable description of what the module does
+    fn description(&self) -> &'static str;
+
+    /// Modules that have to run before this one
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Settings this module accepts in its `[module.<name>]` table
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![]
+    }
+
+    /// The type of settings with keys not in `config_options`. `None` if no other keys are allowed
+    fn additional_config_values(&self) -> Option<ValueType> {
+        None
+    }
+
+    /// Songinfo fields this module reads
+    fn reads(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Songinfo fields this module writes
+    fn writes(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Variables that can be used in the settings of this module, like `%(title)` in a template, with a description
+    fn template_variables(&self) -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    /// Resources this module can't share with other modules running at the same time
+    fn locks(&self) -> &'static [Resource] {
+        &[]
+    }
+
+    /// Runs the module. `instance` is the name the module was listed under in the config,
+    /// e.g. `infocopy@fallback`. Settings are read from the `[module.<instance>]` table
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()>;
+}
+
+/// Returns the name of the module an instance name like `infocopy@fallback` refers to
+pub fn module_name_of(instance: &str) -> &str {
+    instance
+        .split_once('@')
+        .map_or(instance, |(module_name, _)| module_name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// The terminal, used by modules that show a UI
+    Terminal,
+    /// The downloaded audio files
+    AudioFiles,
+}
+
+/// A setting in a modules config table
+pub struct ConfigOption {
+    pub key: &'static str,
+    pub value_type: ValueType,
+    pub default: Option<Value>,
+    pub required: bool,
+    pub description: &'static str,
+}
 
-pub struct ModuleStruct {
-    pub deps: Vec<String>,
-    pub run_function: ModuleRunFunction,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Integer,
+    Boolean,
+    StringArray,
+    Table,
 }
 
-#[macro_export]
-macro_rules! define_module {
-    ($name:expr, $run_function:expr, [$($dependency:expr),*]) => {
-        pub const MODULE_NAME: &str = $name;
+impl ValueType {
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            ValueType::String => value.is_string(),
+            ValueType::Integer => value.is_i64() || value.is_u64(),
+            ValueType::Boolean => value.is_boolean(),
+            ValueType::StringArray => value
+                .as_array()
+                .is_some_and(|a| a.iter().all(Value::is_string)),
+            ValueType::Table => value.is_object(),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::String => "string",
+            ValueType::Integer => "integer",
+            ValueType::Boolean => "boolean",
+            ValueType::StringArray => "array of strings",
+            ValueType::Table => "table",
+        };
+        f.write_str(name)
+    }
+}
+
+/// All modules musicfetch can run, looked up by name
+#[derive(Clone, Default)]
+pub struct ModuleRegistry {
+    modules: Vec<Arc<dyn Module>>,
+}
+
+impl ModuleRegistry {
+    pub fn with_builtin_modules() -> Self {
+        let mut registry = Self::default();
+        let builtin: [Arc<dyn Module>; 11] = [
+            Arc::new(jsonfetch::JsonFetch),
+            Arc::new(infocopy::InfoCopy),
+            Arc::new(download::Download),
+            Arc::new(songcounter::SongCounter),
+            Arc::new(albumcover::AlbumCover),
+            Arc::new(album::AlbumUi),
+            Arc::new(tagui::TagUi),
+            Arc::new(rename::Rename),
+            Arc::new(tag_files::TagFiles),
+            Arc::new(script::Script),
+            Arc::new(rhai_script::RhaiScript),
+        ];
+        for module in builtin {
+            registry
+                .register(module)
+                .expect("Builtin modules have unique names");
+        }
+        registry
+    }
+
+    pub fn register(&mut self, module: Arc<dyn Module>) -> Result<()> {
+        if module.name().contains('@') {
+            bail!("Module name {} must not contain '@'", module.name());
+        }
+        if self.get(module.name()).is_some() {
+            bail!("A module named {} is already registered", module.name());
+        }
+        self.modules.push(module);
+        Ok(())
+    }
+
+    /// Looks up a module by name. Instance names like `infocopy@fallback` return the module they are an instance of
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Module>> {
+        let name = module_name_of(name);
+        self.modules.iter().find(|m| m.name() == name).cloned()
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = &Arc<dyn Module>> {
+        self.modules.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModuleRegistry;
+
+    #[test]
+    fn test_builtin_dependencies_exist() {
+        let registry = ModuleRegistry::with_builtin_modules();
 
-        pub fn module_info() -> ModuleStruct {
-            ModuleStruct {
-                deps: vec![$(
-                    String::from($dependency),
-                )*],
-                run_function: $run_function,
+        for module in registry.modules() {
+            for dependency in module.dependencies() {
+                assert!(
+                    registry.get(dependency).is_some(),
+                    "{} depends on unknown module {}",
+                    module.name(),
+                    dependency
+                );
             }
         }
-    };
-}
-
-pub fn get_module(name: &str) -> Result<ModuleStruct> {
-    Ok(match name {
-        jsonfetch::MODULE_NAME => jsonfetch::module_info(),
-        infocopy::MODULE_NAME => infocopy::module_info(),
-        download::MODULE_NAME => download::module_info(),
-        songcounter::MODULE_NAME => songcounter::module_info(),
-        albumcover::MODULE_NAME => albumcover::module_info(),
-        album::MODULE_NAME => album::module_info(),
-        tagui::MODULE_NAME => tagui::module_info(),
-        rename::MODULE_NAME => rename::module_info(),
-        tag_files::MODULE_NAME => tag_files::module_info(),
-
-        module_name => bail!("No module named {module_name}"),
-    })
+    }
 }
@@ -1,7 +1,8 @@
 use crate::{
-    define_module,
-    module_util::{get_songinfo_field, song_to_string},
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    module_util::{get_song_path, mark_failed, song_to_string},
+    modules::{self, ConfigOption, Module, Resource, ValueType},
+    song::Song,
 };
 
 use fs_extra::file::{self, CopyOptions};
@@ -11,68 +12,189 @@ use serde_json::Value;
 use std::{
     fs::create_dir_all,
     path::PathBuf,
-    str::FromStr,
     sync::{Arc, Mutex},
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
-define_module!("rename", run, [modules::download::MODULE_NAME]);
+pub const MODULE_NAME: &str = "rename";
 
-fn run(global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
+const DEFAULT_TEMPLATE: &str = "%(title).%(ext)";
+
+const TEMPLATE_VARIABLES: [(&str, &str); 8] = [
+    ("title", "Song Title"),
+    ("album", "Album Name"),
+    ("artist", "Artist Name"),
+    ("genre", "Genre"),
+    ("year", "Release year"),
+    ("track_no", "Track Number"),
+    ("total_tracks", "Total Tracks in Album"),
+    ("ext", "File extension of the downloaded file"),
+];
+
+pub struct Rename;
+
+impl Module for Rename {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Moves every song to a path built from a template"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::download::MODULE_NAME]
+    }
+
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![
+            ConfigOption {
+                key: "template",
+                value_type: ValueType::String,
+                default: Some(Value::from(DEFAULT_TEMPLATE)),
+                required: false,
+                description: "Template for filepaths. Can include paths to folders. Variables can be entered like this %(name)",
+            },
+            ConfigOption {
+                key: "copy",
+                value_type: ValueType::Boolean,
+                default: Some(Value::Bool(false)),
+                required: false,
+                description: "Copy the file instead of moving it. The song keeps its old path so later modules still use the original file",
+            },
+        ]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &[
+            "path",
+            "title",
+            "album",
+            "artist",
+            "genre",
+            "year",
+            "track_no",
+            "total_tracks",
+        ]
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &["path", "ext"]
+    }
+
+    fn template_variables(&self) -> &'static [(&'static str, &'static str)] {
+        &TEMPLATE_VARIABLES
+    }
+
+    fn locks(&self) -> &'static [Resource] {
+        &[Resource::AudioFiles]
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
+    }
+}
+
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
     let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
 
     let global = global.lock().unwrap();
-    let global = global.as_object().unwrap();
-    let name_template = match &global["config"]["module"]["rename"]["template"] {
-        Value::String(template) => template.to_owned(),
+    let module_config = global.config.module_config(instance);
+    let name_template = match module_config.and_then(|c| c.get("template")) {
+        Some(Value::String(template)) => template.to_owned(),
         _ => {
             log::warn!("No rename template in config. Using default");
-            String::from("%(title).%(ext)")
+            String::from(DEFAULT_TEMPLATE)
         }
     };
 
-    for song in songs {
-        let ext = get_songinfo_field::<String>(song, "path")?
-            .split('.')
-            .last()
-            .expect("Song path has no file extension");
-        song["songinfo"]["ext"] = Value::from(ext);
+    let copy = module_config
+        .and_then(|c| c.get("copy"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let dry_run = global.args.dry_run;
+    let cancel = global.cancel.clone();
+    drop(global);
 
-        let filename = get_path_for_song(&name_template, song)?;
+    for song in songs.iter_mut().filter(|song| song.is_ok()) {
+        // Stop between songs so no file is left half moved
+        cancel.check()?;
+
+        if let Err(error) = rename_song(&name_template, song, copy, dry_run) {
+            mark_failed(song, instance, error);
+        }
+    }
 
-        let old_path = PathBuf::from_str(get_songinfo_field(song, "path")?)?;
+    Ok(())
+}
 
-        let new_path = PathBuf::from(filename);
+fn rename_song(name_template: &str, song: &mut Song, copy: bool, dry_run: bool) -> Result<()> {
+    let old_path = get_song_path(song)?.to_owned();
 
-        if new_path.is_absolute() && new_path.parent().is_some() {
-            let mut dir = new_path.clone();
-            dir.pop();
+    let ext = old_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .with_context(|| format!("Song path '{}' has no file extension", old_path.display()))?;
+    song.songinfo.ext = Some(ext.to_owned());
 
-            create_dir_all(dir)?;
-        }
+    let filename = get_path_for_song(name_template, song)?;
 
-        info!("renaming file to {}", new_path.display());
-        file::move_file(old_path, &new_path, &CopyOptions::new())?;
+    let new_path = PathBuf::from(filename);
 
-        song["songinfo"]["path"] = Value::from(
-            new_path
-                .to_str()
-                .unwrap_or_else(|| panic!("Filepath for '{}' is not valid utf-8", song)),
+    if new_path == old_path {
+        // Already renamed, for example by a resumed run
+        return Ok(());
+    }
+
+    if dry_run {
+        log::info!(
+            "Would {} '{}' to '{}'",
+            if copy { "copy" } else { "move" },
+            old_path.display(),
+            new_path.display()
         );
+        return Ok(());
     }
 
+    if new_path.is_absolute() && new_path.parent().is_some() {
+        let mut dir = new_path.clone();
+        dir.pop();
+
+        create_dir_all(dir)?;
+    }
+
+    if copy {
+        info!("copying file to {}", new_path.display());
+        // A resumed run copies again, so the copy gets the tags the song has now
+        file::copy(old_path, &new_path, &CopyOptions::new().overwrite(true))?;
+        return Ok(());
+    }
+
+    info!("renaming file to {}", new_path.display());
+    file::move_file(old_path, &new_path, &CopyOptions::new())?;
+
+    song.songinfo.path = Some(new_path);
+
     Ok(())
 }
 
-fn get_path_for_song(path_template: &str, song: &Value) -> Result<String> {
+fn get_path_for_song(path_template: &str, song: &Song) -> Result<String> {
     let mut path = path_template.to_owned();
 
     let re = Regex::new(r"%\((\w+)\)").unwrap();
     for caps in re.captures_iter(path_template) {
         let matched_string = &caps[0];
-        let mut value = match song["songinfo"][&caps[1]].clone() {
+        let mut value = match song.songinfo.get_field(&caps[1]).unwrap_or_default() {
             Value::Null => bail!(
                 "Song '{}' has no field '{}' or field is empty",
                 song_to_string(song),
@@ -96,17 +218,20 @@ fn get_path_for_song(path_template: &str, song: &Value) -> Result<String> {
 mod tests {
     use serde_json::json;
 
+    use crate::song::Song;
+
     use super::get_path_for_song;
 
     #[test]
     fn test_filename_creation() {
-        let song = json!({
+        let song: Song = serde_json::from_value(json!({
             "songinfo": {
                 "title": "Test Song",
                 "artist": "Testartist",
                 "year": 1994,
             }
-        });
+        }))
+        .unwrap();
 
         assert_eq!(
             "Testartist - Test Song",
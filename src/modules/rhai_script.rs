@@ -0,0 +1,179 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rhai::{Dynamic, Engine, ImmutableString, Scope, AST};
+use serde_json::Value;
+
+use crate::{
+    cancel::CancelFlag,
+    global::GlobalContext,
+    module_util::mark_failed,
+    modules::{self, ConfigOption, Module, ValueType, SONGINFO_FIELDS},
+    song::Song,
+};
+
+pub const MODULE_NAME: &str = "rhai";
+
+pub struct RhaiScript;
+
+impl Module for RhaiScript {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Runs a Rhai script for every song. The script can change the map `songinfo` and read the map `yt_dlp`"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![ConfigOption {
+            key: "script",
+            value_type: ValueType::String,
+            default: None,
+            required: true,
+            description: "The Rhai script to run for every song. For example songinfo.title = yt_dlp.title.replace(\" (Official Video)\", \"\");",
+        }]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
+    }
+}
+
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
+    let global = global.lock().unwrap();
+    let Some(script) = global
+        .config
+        .module_config(instance)
+        .and_then(|settings| settings["script"].as_str())
+        .map(|script| script.to_owned())
+    else {
+        bail!("No script given for module {}", instance);
+    };
+    let cancel = global.cancel.clone();
+    drop(global);
+
+    let engine = create_engine(&cancel);
+    let ast = engine
+        .compile(&script)
+        .map_err(|error| anyhow!("Invalid script: {}", error))?;
+
+    let mut songs = songs.lock().unwrap();
+
+    for song in songs.iter_mut().filter(|song| song.is_ok()) {
+        cancel.check()?;
+        if let Err(error) = run_script(&engine, &ast, song) {
+            // A script that was stopped because of the cancel flag didn't fail on its own
+            cancel.check()?;
+            mark_failed(song, instance, error);
+        }
+    }
+
+    Ok(())
+}
+
+fn create_engine(cancel: &CancelFlag) -> Engine {
+    let mut engine = Engine::new();
+
+    // Stops scripts that run long or loop forever once the run is cancelled
+    let cancel = cancel.clone();
+    engine.on_progress(move |_| cancel.is_cancelled().then_some(Dynamic::UNIT));
+
+    // Rhai's own replace only changes the string in place. Returning the result as well
+    // makes `songinfo.title = yt_dlp.title.replace(...)` work
+    engine.register_fn(
+        "replace",
+        |string: &mut ImmutableString, find: &str, substitute: &str| {
+            *string = string.replace(find, substitute).into();
+            string.clone()
+        },
+    );
+
+    engine
+}
+
+/// Runs the script with the songinfo and yt-dlp json of a song and saves the changed songinfo
+fn run_script(engine: &Engine, ast: &AST, song: &mut Song) -> Result<()> {
+    let mut scope = Scope::new();
+    scope.push("songinfo", rhai::serde::to_dynamic(&song.songinfo)?);
+    scope.push("yt_dlp", rhai::serde::to_dynamic(&song.yt_dlp)?);
+
+    engine
+        .run_ast_with_scope(&mut scope, ast)
+        .map_err(|error| anyhow!("Script error: {}", error))?;
+
+    let songinfo = scope
+        .get_value::<Dynamic>("songinfo")
+        .context("The script removed the variable songinfo")?;
+    let songinfo: Value = rhai::serde::from_dynamic(&songinfo)
+        .map_err(|error| anyhow!("Script produced invalid songinfo: {}", error))?;
+
+    song.songinfo = serde_json::from_value(songinfo).context("Script produced invalid songinfo")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{cancel::CancelFlag, song::Song};
+
+    use super::{create_engine, run_script};
+
+    #[test]
+    fn test_run_script() {
+        let mut song: Song = serde_json::from_value(json!({
+            "yt_dlp": { "title": "Test Song (Official Video)", "release_year": 1994 },
+        }))
+        .unwrap();
+        let engine = create_engine(&CancelFlag::default());
+
+        let ast = engine
+            .compile(
+                r#"songinfo.title = yt_dlp.title.replace(" (Official Video)", "");
+                   songinfo.year = yt_dlp.release_year;"#,
+            )
+            .unwrap();
+        run_script(&engine, &ast, &mut song).unwrap();
+
+        assert_eq!(Some(String::from("Test Song")), song.songinfo.title);
+        assert_eq!(Some(1994), song.songinfo.year);
+
+        let ast = engine
+            .compile("let a = 1;\nsonginfo.title = a.nope();")
+            .unwrap();
+        let error = run_script(&engine, &ast, &mut song).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_cancel_script() {
+        let cancel = CancelFlag::default();
+        let engine = create_engine(&cancel);
+        let ast = engine.compile("loop {}").unwrap();
+
+        cancel.cancel();
+        assert!(run_script(&engine, &ast, &mut Song::default()).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    cancel::{CancelFlag, Cancelled},
+    global::GlobalContext,
+    module_util::{mark_failed, merge_into_song},
+    modules::{self, ConfigOption, Module, ValueType, SONGINFO_FIELDS},
+    song::Song,
+};
+
+pub const MODULE_NAME: &str = "script";
+
+const DEFAULT_TIMEOUT: u64 = 60;
+
+pub struct Script;
+
+impl Module for Script {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Runs a command that gets the songs as JSON on stdin and prints the changed songs as JSON on stdout"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![
+            ConfigOption {
+                key: "command",
+                value_type: ValueType::String,
+                default: None,
+                required: true,
+                description: "The program to run",
+            },
+            ConfigOption {
+                key: "args",
+                value_type: ValueType::StringArray,
+                default: Some(Value::Array(vec![])),
+                required: false,
+                description: "Arguments to give to the program",
+            },
+            ConfigOption {
+                key: "timeout",
+                value_type: ValueType::Integer,
+                default: Some(Value::from(DEFAULT_TIMEOUT)),
+                required: false,
+                description: "Seconds after which the program is killed",
+            },
+            ConfigOption {
+                key: "send_global",
+                value_type: ValueType::Boolean,
+                default: Some(Value::Bool(false)),
+                required: false,
+                description: "Also send the command line arguments and the config as 'global'",
+            },
+        ]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
+    }
+}
+
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
+    let global = global.lock().unwrap();
+    let settings = global
+        .config
+        .module_config(instance)
+        .cloned()
+        .unwrap_or_default();
+    let global_value = match settings["send_global"].as_bool() {
+        Some(true) => serde_json::to_value(&*global)?,
+        _ => Value::Null,
+    };
+    let cancel = global.cancel.clone();
+    drop(global);
+
+    let Some(command) = settings["command"].as_str() else {
+        bail!("No command given for module {}", instance);
+    };
+    let args: Vec<&str> = settings["args"]
+        .as_array()
+        .map(|args| args.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let timeout = Duration::from_secs(settings["timeout"].as_u64().unwrap_or(DEFAULT_TIMEOUT));
+
+    let song_list = songs.lock().unwrap().clone();
+    let (indices, sent_songs): (Vec<usize>, Vec<Song>) = song_list
+        .into_iter()
+        .enumerate()
+        .filter(|(_, song)| song.is_ok())
+        .unzip();
+
+    let mut input = json!({ "songs": sent_songs });
+    if !global_value.is_null() {
+        input["global"] = global_value;
+    }
+
+    let output = run_script(command, &args, input.to_string(), timeout, &cancel)?;
+
+    let output: Value = serde_json::from_str(&output)
+        .with_context(|| format!("Script {} printed invalid JSON", command))?;
+    let Some(Value::Array(returned_songs)) = output.get("songs") else {
+        bail!(
+            "Script {} has to print an object with a 'songs' array",
+            command
+        );
+    };
+    if returned_songs.len() != indices.len() {
+        bail!(
+            "Script {} returned {} songs but was given {}",
+            command,
+            returned_songs.len(),
+            indices.len()
+        );
+    }
+
+    let mut songs = songs.lock().unwrap();
+    for (index, returned_song) in indices.into_iter().zip(returned_songs) {
+        let song = &mut songs[index];
+
+        let Value::Object(changes) = returned_song.clone() else {
+            mark_failed(
+                song,
+                instance,
+                anyhow!("Script returned a song that is not an object"),
+            );
+            continue;
+        };
+        if let Err(error) = merge_into_song(song, changes) {
+            mark_failed(
+                song,
+                instance,
+                error.context("Script returned invalid song data"),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the script with `input` on stdin and returns what it printed to stdout
+fn run_script(
+    command: &str,
+    args: &[&str],
+    input: String,
+    timeout: Duration,
+    cancel: &CancelFlag,
+) -> Result<String> {
+    let mut script_process = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start script {}", command))?;
+
+    // Writing and reading happen in their own threads so a script that prints a lot
+    // before reading all of its input can't block us
+    let mut stdin = script_process
+        .stdin
+        .take()
+        .expect("Failed to write to script stdin");
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut stdout = script_process
+        .stdout
+        .take()
+        .expect("Failed to capture script stdout");
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = script_process.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            script_process.kill()?;
+            script_process.wait()?;
+            return Err(Cancelled.into());
+        }
+        if Instant::now() >= deadline {
+            script_process.kill()?;
+            script_process.wait()?;
+            bail!(
+                "Script {} did not finish within {} seconds",
+                command,
+                timeout.as_secs()
+            );
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    // The script doesn't have to read its input, so a broken pipe is fine
+    let _ = writer.join();
+
+    if !status.success() {
+        bail!("Script {} exited with {}", command, status);
+    }
+
+    let output = reader
+        .join()
+        .expect("Reading the script output panicked")
+        .with_context(|| format!("Failed to read the output of script {}", command))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::cancel::CancelFlag;
+
+    use super::run_script;
+
+    #[test]
+    fn test_run_script() {
+        let input = String::from(r#"{"songs":[]}"#);
+        let cancel = CancelFlag::default();
+        let timeout = Duration::from_secs(5);
+
+        let output = run_script("cat", &[], input.clone(), timeout, &cancel).unwrap();
+        assert_eq!(input, output);
+
+        assert!(run_script("false", &[], input.clone(), timeout, &cancel).is_err());
+        let short_timeout = Duration::from_millis(100);
+        assert!(run_script("sleep", &["5"], input.clone(), short_timeout, &cancel).is_err());
+
+        cancel.cancel();
+        assert!(run_script("sleep", &["5"], input, timeout, &cancel).is_err());
+    }
+}
@@ -1,24 +1,61 @@
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
-use serde_json::Value;
 
 use crate::{
-    define_module,
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    modules::{self, Module},
+    song::Song,
 };
 
-define_module!("trackcounter", run, [modules::jsonfetch::MODULE_NAME]);
+pub const MODULE_NAME: &str = "trackcounter";
 
-fn run(_global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
+pub struct SongCounter;
+
+impl Module for SongCounter {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Numbers the songs in the order they were fetched, unless all of them have track numbers already"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &["track_no", "total_tracks"]
+    }
+
+    fn run(
+        &self,
+        _instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(global, songs)
+    }
+}
+
+fn run(_global: Arc<Mutex<GlobalContext>>, songs: Arc<Mutex<Vec<Song>>>) -> Result<()> {
     let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
 
-    let count = songs.len();
+    // Local files usually have their track numbers in their tags already
+    if songs
+        .iter()
+        .filter(|song| song.is_ok())
+        .all(|song| song.songinfo.track_no.is_some())
+    {
+        return Ok(());
+    }
+
+    let count = songs.iter().filter(|song| song.is_ok()).count() as u64;
 
-    for (i, song) in songs.iter_mut().enumerate() {
-        song["songinfo"]["track_no"] = Value::from(i + 1);
-        song["songinfo"]["total_tracks"] = Value::from(count);
+    for (i, song) in songs.iter_mut().filter(|song| song.is_ok()).enumerate() {
+        song.songinfo.track_no = Some(i as u64 + 1);
+        song.songinfo.total_tracks = Some(count);
     }
 
     Ok(())
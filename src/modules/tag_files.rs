@@ -1,68 +1,124 @@
 use std::sync::{Arc, Mutex};
 
 use crate::{
-    define_module,
-    module_util::song_to_string,
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    module_util::{for_each_ok_song, get_song_path},
+    modules::{self, Module, Resource, SONGINFO_FIELDS},
+    song::Song,
 };
 use anyhow::Result;
 use lofty::{read_from_path, Accessor, Tag, TagExt, TaggedFileExt};
-use serde_json::Value;
 
-define_module!("tag_files", run, [modules::download::MODULE_NAME]);
+pub const MODULE_NAME: &str = "tag_files";
 
-fn run(_global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
-    let songs = songs.lock().unwrap();
-    let songs = songs.as_array().unwrap();
+pub struct TagFiles;
 
-    for song in songs {
-        tag_song(song)?;
+impl Module for TagFiles {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Writes the songinfo into the tags of the downloaded files"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::download::MODULE_NAME]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &[
+            "path",
+            "title",
+            "album",
+            "artist",
+            "genre",
+            "year",
+            "track_no",
+            "total_tracks",
+        ]
+    }
+
+    fn locks(&self) -> &'static [Resource] {
+        &[Resource::AudioFiles]
+    }
+
+    fn run(
+        &self,
+        _instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(global, songs)
+    }
+}
+
+fn run(global: Arc<Mutex<GlobalContext>>, songs: Arc<Mutex<Vec<Song>>>) -> Result<()> {
+    let dry_run = global.lock().unwrap().args.dry_run;
+    let mut songs = songs.lock().unwrap();
+
+    if dry_run {
+        for_each_ok_song(&mut songs, MODULE_NAME, |song| print_tags(song));
+    } else {
+        for_each_ok_song(&mut songs, MODULE_NAME, |song| tag_song(song));
     }
 
     Ok(())
 }
 
 // TODO: Option to wipe old tags
-fn tag_song(song: &Value) -> Result<()> {
+fn tag_song(song: &Song) -> Result<()> {
     let mut tag = get_song_tag(song)?;
+    let songinfo = &song.songinfo;
 
-    if let Value::String(title) = &song["songinfo"]["title"] {
+    if let Some(title) = &songinfo.title {
         tag.set_title(title.clone());
     }
-    if let Value::String(album) = &song["songinfo"]["album"] {
+    if let Some(album) = &songinfo.album {
         tag.set_album(album.clone());
     }
-    if let Value::String(artist) = &song["songinfo"]["artist"] {
+    if let Some(artist) = &songinfo.artist {
         tag.set_artist(artist.clone());
     }
-    if let Value::String(genre) = &song["songinfo"]["genre"] {
+    if let Some(genre) = &songinfo.genre {
         tag.set_genre(genre.clone());
     }
-    if let Value::Number(year) = &song["songinfo"]["year"] {
-        tag.set_year(year.as_u64().expect("Year is not a u64 int") as u32);
+    if let Some(year) = songinfo.year {
+        tag.set_year(year as u32);
     }
-    if let Value::Number(track_no) = &song["songinfo"]["track_no"] {
-        tag.set_track(track_no.as_u64().expect("Track No. is not a u64 int") as u32);
+    if let Some(track_no) = songinfo.track_no {
+        tag.set_track(track_no as u32);
     }
-    if let Value::Number(total_tracks) = &song["songinfo"]["total_tracks"] {
-        tag.set_track_total(
-            total_tracks
-                .as_u64()
-                .expect("Total Tracks is not a u64 int") as u32,
-        );
+    if let Some(total_tracks) = songinfo.total_tracks {
+        tag.set_track_total(total_tracks as u32);
     }
 
-    tag.save_to_path(song["songinfo"]["path"].as_str().unwrap())?;
+    tag.save_to_path(get_song_path(song)?)?;
+
+    Ok(())
+}
+
+/// Prints the tags `tag_song` would write
+fn print_tags(song: &Song) -> Result<()> {
+    let fields: Vec<String> = SONGINFO_FIELDS
+        .iter()
+        .filter_map(|field| {
+            song.songinfo
+                .get_field(field)
+                .map(|value| format!("{field}={value}"))
+        })
+        .collect();
 
+    log::info!(
+        "Would write tags to '{}': {}",
+        get_song_path(song)?.display(),
+        fields.join(", ")
+    );
     Ok(())
 }
 
-pub fn get_song_tag(song: &Value) -> Result<Tag> {
-    let mut tagged_file = read_from_path(
-        song["songinfo"]["path"]
-            .as_str()
-            .unwrap_or_else(|| panic!("song '{}' has no path", song_to_string(song))),
-    )?;
+pub fn get_song_tag(song: &Song) -> Result<Tag> {
+    let mut tagged_file = read_from_path(get_song_path(song)?)?;
 
     let tag = match tagged_file.primary_tag_mut() {
         Some(primary_tag) => primary_tag,
@@ -72,7 +128,7 @@ pub fn get_song_tag(song: &Value) -> Result<Tag> {
             } else {
                 let tag_type = tagged_file.primary_tag_type();
 
-                eprintln!("WARN: No tags found, creating a new tag of type `{tag_type:?}`");
+                log::warn!("No tags found, creating a new tag of type `{tag_type:?}`");
                 tagged_file.insert_tag(Tag::new(tag_type));
 
                 tagged_file.primary_tag_mut().unwrap()
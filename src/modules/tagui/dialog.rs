@@ -3,17 +3,18 @@ use cursive::{
     views::{Button, Dialog, DummyView, LinearLayout, SelectView, TextView, EditView}, align::HAlign,
 };
 use cursive_aligned_view::Alignable;
-use serde_json::Value;
 
 use anyhow::Result;
 
+use crate::song::Song;
+
 use super::{
     song_edit::create_song_edit_layout,
     song_select::create_song_select_view,
     util::{get_song_field, remove_non_numeric_chars},
 };
 
-pub fn create_dialog(songs: &Vec<Value>) -> Result<Dialog> {
+pub fn create_dialog(songs: &[Song]) -> Result<Dialog> {
     let dialog = Dialog::around(
         LinearLayout::vertical()
             .child(TextView::new("Edit Tags").center())
@@ -27,10 +28,10 @@ pub fn create_dialog(songs: &Vec<Value>) -> Result<Dialog> {
             .child(create_total_tracks_input(&get_song_field(&songs[0], "total_tracks").unwrap_or_default()))
             .child(Button::new("Save", |siv| {
                 let _songs = siv
-                    .call_on_name("songlist", |v: &mut SelectView<Value>| {
+                    .call_on_name("songlist", |v: &mut SelectView<Song>| {
                         v.iter()
                             .map(|(_, song)| song.to_owned())
-                            .collect::<Vec<Value>>()
+                            .collect::<Vec<Song>>()
                     })
                     .expect("Failed getting songlist from selectview");
 
@@ -57,9 +58,9 @@ fn create_total_tracks_input(initial_value: &str) -> LinearLayout {
                         view.set_content(&total_tracks);
                     });
                     
-                    siv.call_on_name("songlist", |list: &mut SelectView<Value>| {
+                    siv.call_on_name("songlist", |list: &mut SelectView<Song>| {
                         for (_lbl, song) in list.iter_mut() {
-                            song["total_tracks"] = Value::from(total_tracks.parse::<u64>().ok());
+                            song.songinfo.total_tracks = total_tracks.parse::<u64>().ok();
                         }
                     });
                 })
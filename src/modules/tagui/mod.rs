@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use cursive::{
     direction::Direction,
     event::{Event, Key},
@@ -11,15 +11,16 @@ use cursive::{
 use serde_json::Value;
 
 use crate::{
-    define_module,
-    module_util::song_to_string,
-    modules::{self, ModuleStruct},
+    global::GlobalContext,
+    module_util::{accept_without_ui, merge_into_song, required_fields, song_to_string},
+    modules::{self, ConfigOption, Module, Resource, ValueType, SONGINFO_FIELDS},
+    song::Song,
 };
 
 use self::{
     dialog::create_dialog,
     song_select::update_edit_views,
-    util::{compare_songs_by_track_no, get_song_field, merge_b_into_a},
+    util::{compare_songs_by_track_no, get_song_field},
 };
 
 mod dialog;
@@ -27,48 +28,109 @@ mod song_edit;
 mod song_select;
 pub mod util;
 
-define_module!("tagui", run, [modules::jsonfetch::MODULE_NAME]);
+pub const MODULE_NAME: &str = "tagui";
+
+/// Fields that have to be set with `--non-interactive` if the config doesn't list others
+const REQUIRED_FIELDS: [&str; 1] = ["title"];
+
+pub struct TagUi;
+
+impl Module for TagUi {
+    fn name(&self) -> &'static str {
+        MODULE_NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Opens a UI for editing the metadata of each song. With --non-interactive the songs are kept as they are"
+    }
+
+    fn config_options(&self) -> Vec<ConfigOption> {
+        vec![ConfigOption {
+            key: "required_fields",
+            value_type: ValueType::StringArray,
+            default: Some(Value::from(REQUIRED_FIELDS.to_vec())),
+            required: false,
+            description: "Fields that have to be set when running with --non-interactive. Songs missing one of them fail",
+        }]
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[modules::jsonfetch::MODULE_NAME]
+    }
+
+    fn reads(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn writes(&self) -> &'static [&'static str] {
+        &SONGINFO_FIELDS
+    }
+
+    fn locks(&self) -> &'static [Resource] {
+        &[Resource::Terminal]
+    }
+
+    fn run(
+        &self,
+        instance: &str,
+        global: Arc<Mutex<GlobalContext>>,
+        songs: Arc<Mutex<Vec<Song>>>,
+    ) -> Result<()> {
+        run(instance, global, songs)
+    }
+}
+
+fn run(
+    instance: &str,
+    global: Arc<Mutex<GlobalContext>>,
+    songs: Arc<Mutex<Vec<Song>>>,
+) -> Result<()> {
+    let required = {
+        let global = global.lock().unwrap();
+        global
+            .args
+            .non_interactive
+            .then(|| required_fields(&global, instance, &REQUIRED_FIELDS))
+    };
+    if let Some(required) = required {
+        accept_without_ui(instance, &required, &mut songs.lock().unwrap());
+        return Ok(());
+    }
 
-fn run(_global: Arc<Mutex<Value>>, songs: Arc<Mutex<Value>>) -> Result<()> {
     let mut siv = init_cursive(Arc::clone(&songs))?;
 
-    siv.run_crossterm()
-        .expect("TUI initialization failed. Try using another Terminal");
+    siv.run_crossterm().context(
+        "TUI initialization failed. Try using another Terminal or run with --non-interactive",
+    )?;
 
     let mut songs = songs.lock().unwrap();
-    let songs = songs.as_array_mut().unwrap();
 
     // TODO: Quit silently
-    let new_songs = Value::from(
-        siv.take_user_data::<Vec<Value>>()
-            .expect("Could not get Cursive user data."),
-    );
-    let new_songs = new_songs.as_array().unwrap();
-
-    for (song, new_song) in songs.iter_mut().zip(new_songs.iter()) {
-        let song_obj = song
-            .as_object_mut()
-            .expect("Song is not an Object, this is an Error.");
-        let new_song_obj = new_song
-            .as_object()
-            .expect("Song is not an Object, this is an Error.");
-
-        merge_b_into_a(song_obj, new_song_obj.clone());
+    let new_songs = siv
+        .take_user_data::<Vec<Song>>()
+        .expect("Could not get Cursive user data.");
+
+    for (song, new_song) in songs.iter_mut().zip(new_songs) {
+        let Value::Object(new_song_obj) = serde_json::to_value(new_song)? else {
+            panic!("Song is not an Object, this is an Error.");
+        };
+
+        // Other modules might have changed the status while the UI was open, it is kept
+        merge_into_song(song, new_song_obj).context("Tagui returned invalid song data")?;
     }
 
     Ok(())
 }
 
 // TODO: Prevent saving if there are songs with missing fields
-pub fn init_cursive(songs: Arc<Mutex<Value>>) -> Result<Cursive> {
+pub fn init_cursive(songs: Arc<Mutex<Vec<Song>>>) -> Result<Cursive> {
     let mut siv = Cursive::default();
 
     siv.set_theme(Theme::terminal_default());
 
     let songs = songs.lock().unwrap();
-    let songs = songs.as_array().unwrap();
 
-    siv.add_layer(create_dialog(songs)?);
+    siv.add_layer(create_dialog(&songs)?);
 
     add_global_callbacks(&mut siv);
 
@@ -94,13 +156,13 @@ fn add_global_callbacks(siv: &mut Cursive) {
     // Callbacks for changing selected song anywhere
     siv.add_global_callback(Event::Key(Key::PageUp), |siv| {
         let cb = siv
-            .call_on_name("songlist", |list: &mut SelectView<Value>| list.select_up(1))
+            .call_on_name("songlist", |list: &mut SelectView<Song>| list.select_up(1))
             .expect("UI Error");
         cb(siv);
     });
     siv.add_global_callback(Event::Key(Key::PageDown), |siv| {
         let cb = siv
-            .call_on_name("songlist", |list: &mut SelectView<Value>| {
+            .call_on_name("songlist", |list: &mut SelectView<Song>| {
                 list.select_down(1)
             })
             .expect("UI Error");
@@ -114,20 +176,20 @@ enum ChangeType {
 }
 
 fn change_track_no_for_current_song(siv: &mut Cursive, change: ChangeType) {
-    siv.call_on_name("songlist", |list: &mut SelectView<Value>| {
+    siv.call_on_name("songlist", |list: &mut SelectView<Song>| {
         let Some(song) = list.selected_id() else {
             return;
         };
         if let Some((_, song)) = list.get_item_mut(song) {
             let new_value = match change {
                 ChangeType::Relative(i) => {
-                    let track_no = song["songinfo"]["track_no"].as_u64().unwrap_or(1);
-                    Value::from((track_no as i32 + i).max(0))
+                    let track_no = song.songinfo.track_no.unwrap_or(1);
+                    (track_no as i32 + i).max(0)
                 }
-                ChangeType::Absolute(i) => Value::from(i.max(0)),
+                ChangeType::Absolute(i) => i.max(0),
             };
 
-            song["songinfo"]["track_no"] = new_value;
+            song.songinfo.track_no = Some(new_value as u64);
         }
     });
     refresh_songlist(siv);
@@ -137,7 +199,7 @@ fn change_track_no_for_current_song(siv: &mut Cursive, change: ChangeType) {
 
 // TODO: Highlight Songs with missing fields
 fn refresh_songlist(siv: &mut Cursive) {
-    siv.call_on_name("songlist", |songlist: &mut SelectView<Value>| {
+    siv.call_on_name("songlist", |songlist: &mut SelectView<Song>| {
         // Get the currently selected song
         let sel = songlist
             .selection()
@@ -160,7 +222,7 @@ fn refresh_songlist(siv: &mut Cursive) {
     });
 }
 
-fn refresh_songlist_labels(songlist: &mut SelectView<Value>) {
+fn refresh_songlist_labels(songlist: &mut SelectView<Song>) {
     for (label, song) in songlist.iter_mut() {
         label.remove_spans(0..1);
         label.compact();
@@ -7,17 +7,19 @@ use serde_json::Value;
 
 use anyhow::Result;
 
-use crate::module_util::song_to_string;
+use crate::{module_util::song_to_string, song::Song};
 
 use super::{
     refresh_songlist,
     util::{get_song_field, remove_non_numeric_chars, set_song_field},
 };
 
+type EditCallback = Box<dyn Fn(&mut Cursive, &str)>;
+
 fn create_edit_view_for_song_field(
-    first_song: &Value,
+    first_song: &Song,
     field: &str,
-    on_edit_alt: Option<Box<dyn Fn(&mut Cursive, &str)>>,
+    on_edit_alt: Option<EditCallback>,
 ) -> Result<NamedView<EditView>> {
     let cloned_field = field.to_owned();
 
@@ -34,7 +36,7 @@ fn create_edit_view_for_song_field(
 }
 
 // Extensive refactoring has gone into this function to make it as legible as possible. I'm not entirely happy with it yet so feel free to improve it
-pub fn create_song_edit_layout(first_song: &Value) -> Result<LinearLayout> {
+pub fn create_song_edit_layout(first_song: &Song) -> Result<LinearLayout> {
     let header = ResizedView::with_fixed_height(
         3,
         TextView::new(song_to_string(first_song))
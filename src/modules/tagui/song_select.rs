@@ -3,17 +3,15 @@ use cursive::{
     views::{EditView, NamedView, ScrollView, SelectView, TextView},
     Cursive,
 };
-use serde_json::Value;
 
-use crate::module_util::song_to_string;
+use crate::{module_util::song_to_string, song::Song};
 
 use super::util::get_song_field;
 
-pub fn create_song_select_view(songs: &Vec<Value>) -> ScrollView<NamedView<SelectView<Value>>> {
-    let cloned = songs.clone();
-    let song_names = cloned.iter().map(song_to_string);
+pub fn create_song_select_view(songs: &[Song]) -> ScrollView<NamedView<SelectView<Song>>> {
+    let song_names = songs.iter().map(song_to_string);
 
-    let selectview_items = song_names.zip(songs.clone()).map(|(label, song)| {
+    let selectview_items = song_names.zip(songs.to_owned()).map(|(label, song)| {
         (
             format!(
                 "{} {}",
@@ -35,7 +33,7 @@ pub fn create_song_select_view(songs: &Vec<Value>) -> ScrollView<NamedView<Selec
 /// Updates the contents of the edit views to match the song selected
 pub fn update_edit_views(siv: &mut Cursive) {
     let Some(song) = siv
-        .call_on_name("songlist", |list: &mut SelectView<Value>| list.selection())
+        .call_on_name("songlist", |list: &mut SelectView<Song>| list.selection())
         .unwrap()
     else {
         return;
@@ -0,0 +1,628 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Instant,
+};
+
+use anyhow::{anyhow, Context, Error, Result};
+
+use crate::{
+    cancel::{CancelFlag, Cancelled},
+    cmdline::Args,
+    config::{get_config, Config},
+    events::{Event, EventSink},
+    global::GlobalContext,
+    logging,
+    module_util::song_to_string,
+    modules::{module_name_of, Module, ModuleRegistry, Resource},
+    schedule,
+    song::{Song, SongStatus},
+    state::RunState,
+    validate,
+};
+
+/// Runs a set of modules on songs. This is what the musicfetch command line uses under the hood
+///
+/// ```no_run
+/// use musicfetch::Pipeline;
+///
+/// let result = Pipeline::from_config_name("default")?
+///     .urls(vec![String::from("https://www.youtube.com/watch?v=dQw4w9WgXcQ")])
+///     .run()?;
+///
+/// for song in result.songs {
+///     println!("{:?}", song.songinfo.path);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Pipeline {
+    registry: ModuleRegistry,
+    config: Config,
+    args: Args,
+    songs: Vec<Song>,
+    resume_state: Option<RunState>,
+    state_file: Option<PathBuf>,
+    cancel: CancelFlag,
+    events: EventSink,
+}
+
+/// What is left after a pipeline ran
+pub struct PipelineResult {
+    pub songs: Vec<Song>,
+    /// The modules that failed with their errors
+    pub failed_modules: Vec<(String, Error)>,
+    /// `true` if the run was cancelled before all stages ran
+    pub cancelled: bool,
+}
+
+impl PipelineResult {
+    /// `true` if no module and no song failed
+    pub fn success(&self) -> bool {
+        !self.cancelled
+            && self.failed_modules.is_empty()
+            && !self
+                .songs
+                .iter()
+                .any(|song| matches!(song.status, SongStatus::Failed { .. }))
+    }
+}
+
+impl Pipeline {
+    /// Creates a pipeline with the builtin modules that runs the modules from `config`
+    pub fn new(config: Config) -> Self {
+        Pipeline {
+            registry: ModuleRegistry::with_builtin_modules(),
+            config,
+            args: Args::default(),
+            songs: vec![],
+            resume_state: None,
+            state_file: None,
+            cancel: CancelFlag::default(),
+            events: EventSink::default(),
+        }
+    }
+
+    /// Creates a pipeline using the config with this name from the musicfetch config directory
+    pub fn from_config_name(name: &str) -> Result<Self> {
+        let config = get_config(name).context("Failed to load config")?;
+        Ok(Self::new(config))
+    }
+
+    /// Makes a module available to the config. Fails if a module with the same name exists
+    pub fn register_module(mut self, module: Arc<dyn Module>) -> Result<Self> {
+        self.registry.register(module)?;
+        Ok(self)
+    }
+
+    /// Sets the options modules see as the command line arguments
+    pub fn args(mut self, args: Args) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Sets the urls `fetch_song_info` fetches the songs from
+    pub fn urls(mut self, urls: Vec<String>) -> Self {
+        self.args.urls = urls;
+        self
+    }
+
+    /// Sets the songs the pipeline starts with. Songs fetched from urls are added after them
+    pub fn songs(mut self, songs: Vec<Song>) -> Self {
+        self.songs = songs;
+        self
+    }
+
+    /// Saves the state after every stage to this file so the run can be resumed.
+    /// The file is removed if the run finishes without errors
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Continues a run from its saved state instead of starting from the beginning
+    pub fn resume(mut self, state: RunState) -> Self {
+        self.resume_state = Some(state);
+        self
+    }
+
+    /// Returns a flag that stops the run when it is cancelled. Modules are asked to stop, running
+    /// processes are killed and no further stages are started
+    pub fn cancel_flag(&self) -> CancelFlag {
+        self.cancel.clone()
+    }
+
+    /// Uses a flag that is shared with other pipelines, so one Ctrl-C handler can cancel all of them
+    pub fn cancel_with(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Reports what happens during the run to `events`, for example to follow the progress from another program
+    pub fn events(mut self, events: EventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Checks the config, puts the modules into stages and runs them
+    pub fn run(self) -> Result<PipelineResult> {
+        validate::validate_config(&self.config, &self.registry)?;
+        let stages = schedule::get_stages(&self.config, &self.registry)
+            .context("Failed to schedule modules")?;
+
+        let (first_stage, songs) = match self.resume_state {
+            Some(state) => state.resume_point(&stages)?,
+            None => (0, self.songs),
+        };
+
+        let global_data = Arc::new(Mutex::new(GlobalContext {
+            args: self.args,
+            config: self.config,
+            cancel: self.cancel.clone(),
+            events: self.events.clone(),
+        }));
+        let song_data = Arc::new(Mutex::new(songs));
+
+        let failed_modules = run_stages(
+            &self.registry,
+            &stages,
+            first_stage,
+            self.state_file.as_deref(),
+            Arc::clone(&global_data),
+            Arc::clone(&song_data),
+        );
+
+        let songs = song_data
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let result = PipelineResult {
+            songs,
+            failed_modules,
+            cancelled: self.cancel.is_cancelled(),
+        };
+
+        if !result.cancelled {
+            for (i, song) in result
+                .songs
+                .iter()
+                .enumerate()
+                .filter(|(_, song)| song.is_ok())
+            {
+                self.events.emit(Event::SongFinished {
+                    song: i,
+                    title: song_to_string(song),
+                    path: song.songinfo.path.clone(),
+                });
+            }
+        }
+        self.events.emit(Event::RunFinished {
+            success: result.success(),
+            cancelled: result.cancelled,
+        });
+
+        if let Some(state_file) = &self.state_file {
+            if result.success() {
+                // Nothing left to resume
+                let _ = fs::remove_file(state_file);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Runs the stages one after another, starting at `first_stage`. Returns the modules that failed with their errors.
+///
+/// After every stage the state is saved to `state_file` so a failed run can be resumed.
+/// Once the cancel flag of the global context is set no further stages are started
+fn run_stages(
+    registry: &ModuleRegistry,
+    stages: &[Vec<String>],
+    first_stage: usize,
+    state_file: Option<&Path>,
+    global_data: Arc<Mutex<GlobalContext>>,
+    song_data: Arc<Mutex<Vec<Song>>>,
+) -> Vec<(String, Error)> {
+    let (cancel, events) = {
+        let global = global_data.lock().unwrap();
+        (global.cancel.clone(), global.events.clone())
+    };
+    let mut failed_modules: Vec<(String, Error)> = vec![];
+    let mut next_stage = first_stage;
+
+    for (i, stage) in stages.iter().enumerate().skip(first_stage) {
+        if cancel.is_cancelled() {
+            break;
+        }
+        log::info!("Running stage {}...", i + 1);
+        events.emit(Event::StageStarted {
+            stage: i + 1,
+            modules: stage.clone(),
+        });
+        let stage_start = Instant::now();
+
+        let failed_modules_before = failed_modules.len();
+        let statuses_before = song_statuses(&song_data);
+
+        let mut handles = vec![];
+
+        for module_name in stage.clone() {
+            let module = registry
+                .get(&module_name)
+                .expect("Modules are validated before running");
+
+            let failed_dependency = module.dependencies().iter().find(|dependency| {
+                failed_modules
+                    .iter()
+                    .any(|(name, _)| module_name_of(name) == **dependency)
+            });
+            if let Some(dependency) = failed_dependency {
+                log::warn!("Skipping module {module_name} because {dependency} failed");
+                events.emit(Event::ModuleFinished {
+                    module: module_name.clone(),
+                    duration_ms: 0,
+                    error: Some(format!("Skipped because {dependency} failed")),
+                });
+                failed_modules.push((module_name, anyhow!("Skipped because {dependency} failed")));
+                continue;
+            }
+
+            let _global = Arc::clone(&global_data);
+            let _songs = Arc::clone(&song_data);
+
+            if module.locks().contains(&Resource::Terminal) {
+                logging::pause_stderr();
+            }
+
+            // The logger uses the thread name to tell which module a log line is from
+            let instance = module_name.clone();
+            events.emit(Event::ModuleStarted {
+                module: module_name.clone(),
+            });
+            let module_start = Instant::now();
+            let spawned = thread::Builder::new()
+                .name(module_name.clone())
+                .spawn(move || module.run(&instance, _global, _songs));
+            match spawned {
+                Ok(handle) => handles.push((module_name, module_start, handle)),
+                Err(error) => {
+                    log::error!("Failed to start module {module_name}: {error}");
+                    events.emit(Event::ModuleFinished {
+                        module: module_name.clone(),
+                        duration_ms: 0,
+                        error: Some(error.to_string()),
+                    });
+                    failed_modules.push((module_name, error.into()));
+                }
+            }
+        }
+
+        for (module_name, module_start, handle) in handles {
+            let result = handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("Module {module_name} panicked")));
+
+            events.emit(Event::ModuleFinished {
+                module: module_name.clone(),
+                duration_ms: module_start.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|error| format!("{error:#}")),
+            });
+            if let Err(error) = result {
+                if error.is::<Cancelled>() {
+                    log::info!("Module {module_name} was cancelled");
+                } else {
+                    log::error!("Error in module {module_name}: {error:#}");
+                }
+                failed_modules.push((module_name, error));
+            }
+        }
+        logging::resume_stderr();
+
+        emit_song_status_events(&events, &statuses_before, &song_data);
+        events.emit(Event::StageFinished {
+            stage: i + 1,
+            duration_ms: stage_start.elapsed().as_millis() as u64,
+        });
+
+        // Songs that failed are retried from their own stage on resume, so only a module that
+        // failed as a whole or was cancelled keeps the run at this stage
+        let stage_succeeded = failed_modules.len() == failed_modules_before;
+        if stage_succeeded && next_stage == i {
+            next_stage = i + 1;
+        }
+
+        if let Some(state_file) = state_file {
+            save_state(state_file, next_stage, stages, &global_data, &song_data);
+        }
+    }
+    failed_modules
+}
+
+fn song_statuses(song_data: &Mutex<Vec<Song>>) -> Vec<SongStatus> {
+    song_data
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|song| song.status.clone())
+        .collect()
+}
+
+/// Reports the songs that failed since `statuses_before` was taken
+fn emit_song_status_events(
+    events: &EventSink,
+    statuses_before: &[SongStatus],
+    song_data: &Mutex<Vec<Song>>,
+) {
+    let songs = song_data.lock().unwrap_or_else(PoisonError::into_inner);
+
+    for (i, song) in songs.iter().enumerate() {
+        if statuses_before
+            .get(i)
+            .is_some_and(|status| *status == song.status)
+        {
+            continue;
+        }
+        if let SongStatus::Failed { module, error } = &song.status {
+            events.emit(Event::SongFailed {
+                song: i,
+                title: song_to_string(song),
+                module: module.clone(),
+                error: error.clone(),
+            });
+        }
+    }
+}
+
+fn save_state(
+    state_file: &Path,
+    next_stage: usize,
+    stages: &[Vec<String>],
+    global_data: &Mutex<GlobalContext>,
+    song_data: &Mutex<Vec<Song>>,
+) {
+    let state = RunState {
+        next_stage,
+        stages: stages.to_vec(),
+        global: global_data
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+        songs: song_data
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+    };
+
+    if let Err(error) = state.save(state_file) {
+        log::error!("Failed to save the state of the run: {error:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::{anyhow, bail, Result};
+    use serde_json::{json, Value};
+
+    use crate::{
+        cancel::CancelFlag, config::Config, events::EventSink, global::GlobalContext,
+        module_util::mark_failed, modules::Module, song::Song, state::RunState,
+    };
+
+    use super::Pipeline;
+
+    struct Shout;
+
+    impl Module for Shout {
+        fn name(&self) -> &'static str {
+            "shout"
+        }
+
+        fn description(&self) -> &'static str {
+            "Makes every title uppercase"
+        }
+
+        fn run(
+            &self,
+            _instance: &str,
+            _global: Arc<Mutex<GlobalContext>>,
+            songs: Arc<Mutex<Vec<Song>>>,
+        ) -> Result<()> {
+            for song in songs.lock().unwrap().iter_mut() {
+                song.songinfo.title = song.songinfo.title.as_ref().map(|t| t.to_uppercase());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_with_custom_module() {
+        let config: Config = serde_json::from_value(json!({ "modules": ["shout"] })).unwrap();
+        let song: Song =
+            serde_json::from_value(json!({ "songinfo": { "title": "Test Song" } })).unwrap();
+
+        let result = Pipeline::new(config)
+            .register_module(Arc::new(Shout))
+            .unwrap()
+            .songs(vec![song])
+            .run()
+            .unwrap();
+
+        assert!(result.success());
+        assert_eq!(
+            Some(String::from("TEST SONG")),
+            result.songs[0].songinfo.title
+        );
+    }
+
+    /// Fails the first song, like a video that was deleted
+    struct FailFirst;
+
+    impl Module for FailFirst {
+        fn name(&self) -> &'static str {
+            "fail_first"
+        }
+
+        fn description(&self) -> &'static str {
+            "Fails the first song"
+        }
+
+        fn run(
+            &self,
+            instance: &str,
+            _global: Arc<Mutex<GlobalContext>>,
+            songs: Arc<Mutex<Vec<Song>>>,
+        ) -> Result<()> {
+            mark_failed(&mut songs.lock().unwrap()[0], instance, anyhow!("Deleted"));
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl Module for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn description(&self) -> &'static str {
+            "Always fails"
+        }
+
+        fn run(
+            &self,
+            _instance: &str,
+            _global: Arc<Mutex<GlobalContext>>,
+            _songs: Arc<Mutex<Vec<Song>>>,
+        ) -> Result<()> {
+            bail!("Broken")
+        }
+    }
+
+    #[test]
+    fn test_failed_song_does_not_hold_back_the_run() {
+        let config: Config = serde_json::from_value(json!({
+            "stages": { "stage1": ["fail_first"], "stage2": ["shout"], "stage3": ["broken"] }
+        }))
+        .unwrap();
+        let songs: Vec<Song> = serde_json::from_value(json!([
+            { "songinfo": { "title": "Deleted" } },
+            { "songinfo": { "title": "Test Song" } },
+        ]))
+        .unwrap();
+        let state_file = env::temp_dir().join(format!(
+            "musicfetch-pipeline-test-{}.json",
+            std::process::id()
+        ));
+
+        let result = Pipeline::new(config)
+            .register_module(Arc::new(FailFirst))
+            .unwrap()
+            .register_module(Arc::new(Shout))
+            .unwrap()
+            .register_module(Arc::new(Broken))
+            .unwrap()
+            .songs(songs)
+            .state_file(&state_file)
+            .run()
+            .unwrap();
+        let state = RunState::load(&state_file);
+        let _ = fs::remove_file(&state_file);
+
+        assert!(!result.success());
+        let state = state.unwrap();
+        assert_eq!(2, state.next_stage);
+        assert_eq!(
+            Some(String::from("TEST SONG")),
+            state.songs[1].songinfo.title
+        );
+    }
+
+    /// Collects what is written to an [`EventSink`]
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_events() {
+        let config: Config = serde_json::from_value(json!({ "modules": ["shout"] })).unwrap();
+        let song: Song = serde_json::from_value(json!({
+            "songinfo": { "title": "Test Song", "path": "test.mp3" }
+        }))
+        .unwrap();
+        let captured = Captured::default();
+
+        Pipeline::new(config)
+            .register_module(Arc::new(Shout))
+            .unwrap()
+            .songs(vec![song])
+            .events(EventSink::new(Box::new(captured.clone())))
+            .run()
+            .unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            vec![
+                "stage_started",
+                "module_started",
+                "module_finished",
+                "stage_finished",
+                "song_finished",
+                "run_finished"
+            ],
+            names
+        );
+        assert_eq!(
+            json!({ "event": "song_finished", "song": 0, "title": "TEST SONG", "path": "test.mp3" }),
+            events[4]
+        );
+        assert_eq!(
+            json!({ "event": "run_finished", "success": true, "cancelled": false }),
+            events[5]
+        );
+
+        // A cancelled run only reports that it ended
+        let cancel = CancelFlag::default();
+        cancel.cancel();
+        let captured = Captured::default();
+        let config: Config = serde_json::from_value(json!({ "modules": ["shout"] })).unwrap();
+        Pipeline::new(config)
+            .register_module(Arc::new(Shout))
+            .unwrap()
+            .cancel_with(cancel)
+            .events(EventSink::new(Box::new(captured.clone())))
+            .run()
+            .unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            "{\"event\":\"run_finished\",\"success\":false,\"cancelled\":true}\n",
+            output
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config::Config, validate};
+
+/// A `[profiles.<config name>]` table. Urls that match it are run with the config of the same name
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    /// Regular expressions matched against the urls
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// `extractor_key` values of yt-dlp, like "Youtube" or "BandcampAlbum"
+    #[serde(default)]
+    pub extractors: Vec<String>,
+}
+
+/// The profiles of a config, ready to match urls against
+#[derive(Default)]
+pub struct Profiles {
+    profiles: Vec<(String, Vec<Regex>, Vec<String>)>,
+}
+
+impl Profiles {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut profiles = vec![];
+        for (name, profile) in &config.profiles {
+            let mut patterns = vec![];
+            for (i, pattern) in profile.urls.iter().enumerate() {
+                let location = config.sources.value(&["profiles", name, "urls"]);
+                let regex = Regex::new(pattern).map_err(|error| {
+                    anyhow!(validate::at(
+                        location,
+                        format!(
+                            "Url pattern {} of profile {name} is invalid: {error}",
+                            i + 1
+                        )
+                    ))
+                })?;
+                patterns.push(regex);
+            }
+            profiles.push((name.clone(), patterns, profile.extractors.clone()));
+        }
+        Ok(Profiles { profiles })
+    }
+
+    /// Finds the config for a url. Url patterns are tried first. Only if none of them matches
+    /// `extractor_key` is asked for the yt-dlp extractor of the url. If more than one profile
+    /// matches the first one by name is used
+    pub fn select(
+        &self,
+        url: &str,
+        extractor_key: impl FnOnce(&str) -> Result<Option<String>>,
+    ) -> Result<Option<&str>> {
+        let by_url = self
+            .profiles
+            .iter()
+            .find(|(_, patterns, _)| patterns.iter().any(|pattern| pattern.is_match(url)));
+        if let Some((name, _, _)) = by_url {
+            return Ok(Some(name));
+        }
+
+        if self
+            .profiles
+            .iter()
+            .all(|(_, _, extractors)| extractors.is_empty())
+        {
+            return Ok(None);
+        }
+        let Some(key) = extractor_key(url)? else {
+            return Ok(None);
+        };
+        let by_extractor = self.profiles.iter().find(|(_, _, extractors)| {
+            extractors
+                .iter()
+                .any(|extractor| extractor.eq_ignore_ascii_case(&key))
+        });
+        Ok(by_extractor.map(|(name, _, _)| name.as_str()))
+    }
+
+    /// Finds the config for a url, asking yt-dlp for its extractor if needed
+    pub fn config_for(&self, url: &str) -> Result<Option<&str>> {
+        self.select(url, fetch_extractor_key)
+    }
+}
+
+/// Asks yt-dlp which extractor handles a url. Playlists are not expanded, so this is a single request
+fn fetch_extractor_key(url: &str) -> Result<Option<String>> {
+    log::debug!("Asking yt-dlp for the extractor of {url}");
+    let output = Command::new("yt-dlp")
+        .args(["--flat-playlist", "-J", url])
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Running yt-dlp command failed. Check if it is installed")?;
+
+    if !output.status.success() {
+        log::warn!("yt-dlp could not find the extractor of {url}, using the default config");
+        return Ok(None);
+    }
+    let json: Value =
+        serde_json::from_slice(&output.stdout).context("Parsing yt-dlp output failed.")?;
+    Ok(json["extractor_key"].as_str().map(str::to_owned))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    use super::Profiles;
+
+    #[test]
+    fn test_select_profile() {
+        let config: Config = toml::from_str(
+            r#"
+[profiles.bandcamp]
+urls = ['^https://[^/]*\.bandcamp\.com/']
+
+[profiles.soundcloud]
+extractors = ["Soundcloud"]
+"#,
+        )
+        .unwrap();
+        let profiles = Profiles::new(&config).unwrap();
+        let no_lookup = |_: &str| panic!("The extractor should not be looked up");
+
+        assert_eq!(
+            Some("bandcamp"),
+            profiles
+                .select("https://artist.bandcamp.com/album/x", no_lookup)
+                .unwrap()
+        );
+        assert_eq!(
+            Some("soundcloud"),
+            profiles
+                .select("https://on.soundcloud.com/abc", |_| Ok(Some(String::from(
+                    "soundcloud"
+                ))))
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            profiles
+                .select("https://youtu.be/abc", |_| Ok(Some(String::from(
+                    "Youtube"
+                ))))
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::Config,
+    modules::{Module, ModuleRegistry},
+};
+
+/// Returns the stages to run. The modules in each stage are run in parallel
+pub fn get_stages(config: &Config, registry: &ModuleRegistry) -> Result<Vec<Vec<String>>> {
+    if !config.stages.is_empty() {
+        return Ok(get_explicit_stages(config)
+            .into_iter()
+            .map(|(_, stage)| stage)
+            .collect());
+    }
+    schedule_modules(&config.modules, registry)
+}
+
+/// Returns the stages from the `[stages]` table with their numbers, ordered by number.
+/// Numbers don't have to be consecutive, so `stage15` can be put between `stage10` and `stage20`
+pub fn get_explicit_stages(config: &Config) -> Vec<(u32, Vec<String>)> {
+    let mut stages: Vec<(u32, Vec<String>)> = config
+        .stages
+        .iter()
+        .filter_map(|(key, stage)| Some((stage_number(key)?, stage.clone())))
+        .collect();
+    stages.sort_by_key(|(number, _)| *number);
+    stages
+}
+
+pub const MAX_STAGE: u32 = 99;
+
+/// Parses the number out of a stage name like `stage15`. Returns `None` for invalid names
+pub fn stage_number(key: &str) -> Option<u32> {
+    let digits = key.strip_prefix("stage")?;
+    let number: u32 = digits.parse().ok()?;
+
+    // Reject leading zeros and signs so every stage has exactly one name
+    if number.to_string() != digits || !(1..=MAX_STAGE).contains(&number) {
+        return None;
+    }
+    Some(number)
+}
+
+/// Puts modules into stages using their dependencies and the songinfo fields they read and write.
+///
+/// Modules that touch the same fields or lock the same resource run in the order they are listed in.
+/// Every module is put into the latest stage that still runs before all modules that need it. That way
+/// slow modules like download run alongside the interactive ones instead of holding them up.
+pub fn schedule_modules(
+    module_names: &[String],
+    registry: &ModuleRegistry,
+) -> Result<Vec<Vec<String>>> {
+    let modules = module_names
+        .iter()
+        .map(|name| {
+            registry
+                .get(name)
+                .with_context(|| format!("No module named {name}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if modules.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let order = order_by_dependencies(&modules)?;
+
+    // The positions in `order` of the modules that have to run before the module at each position
+    let predecessors: Vec<Vec<usize>> = (0..order.len())
+        .map(|pos| {
+            (0..pos)
+                .filter(|&prev| {
+                    must_run_before(modules[order[prev]].as_ref(), modules[order[pos]].as_ref())
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut earliest = vec![0; order.len()];
+    for pos in 0..order.len() {
+        earliest[pos] = predecessors[pos]
+            .iter()
+            .map(|&prev| earliest[prev] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+    let last_stage = earliest.iter().copied().max().unwrap_or(0);
+
+    let mut latest = vec![last_stage; order.len()];
+    for pos in (0..order.len()).rev() {
+        for &prev in &predecessors[pos] {
+            latest[prev] = latest[prev].min(latest[pos] - 1);
+        }
+    }
+
+    let mut stage_of_module = vec![0; modules.len()];
+    for (pos, &module) in order.iter().enumerate() {
+        stage_of_module[module] = latest[pos];
+    }
+
+    let mut stages = vec![vec![]; last_stage + 1];
+    for (name, stage) in module_names.iter().zip(stage_of_module) {
+        stages[stage].push(name.to_owned());
+    }
+    Ok(stages)
+}
+
+/// Sorts the modules so every module comes after its dependencies.
+/// Apart from that the modules keep the order they were given in
+fn order_by_dependencies(modules: &[Arc<dyn Module>]) -> Result<Vec<usize>> {
+    for module in modules {
+        for dependency in module.dependencies() {
+            if !modules.iter().any(|m| m.name() == *dependency) {
+                bail!(
+                    "Module {} depends on {dependency} but {dependency} is not in the list of modules",
+                    module.name()
+                );
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = vec![];
+    while order.len() < modules.len() {
+        let next = (0..modules.len()).find(|i| {
+            !order.contains(i)
+                && modules[*i]
+                    .dependencies()
+                    .iter()
+                    .all(|dependency| order.iter().any(|&j| modules[j].name() == *dependency))
+        });
+
+        let Some(next) = next else {
+            let remaining = (0..modules.len())
+                .filter(|i| !order.contains(i))
+                .map(|i| modules[i].name())
+                .collect::<Vec<_>>();
+            bail!("The modules {} depend on each other", remaining.join(", "));
+        };
+        order.push(next);
+    }
+    Ok(order)
+}
+
+fn must_run_before(first: &dyn Module, second: &dyn Module) -> bool {
+    second.dependencies().contains(&first.name()) || conflicts(first, second)
+}
+
+fn conflicts(a: &dyn Module, b: &dyn Module) -> bool {
+    let overlaps = |x: &[&str], y: &[&str]| x.iter().any(|field| y.contains(field));
+
+    overlaps(a.writes(), b.writes())
+        || overlaps(a.writes(), b.reads())
+        || overlaps(a.reads(), b.writes())
+        || a.locks()
+            .iter()
+            .any(|resource| b.locks().contains(resource))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{config::Config, modules::ModuleRegistry};
+
+    use super::{get_explicit_stages, schedule_modules, stage_number};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_schedule_default_modules() {
+        let registry = ModuleRegistry::with_builtin_modules();
+        let modules = names(&[
+            "fetch_song_info",
+            "infocopy",
+            "albumui",
+            "trackcounter",
+            "tagui",
+            "download",
+            "tag_files",
+            "albumcover",
+            "rename",
+        ]);
+
+        let stages = schedule_modules(&modules, &registry).unwrap();
+
+        assert_eq!(
+            vec![
+                names(&["fetch_song_info"]),
+                names(&["infocopy"]),
+                names(&["albumui", "trackcounter"]),
+                names(&["tagui", "download"]),
+                names(&["tag_files"]),
+                names(&["albumcover"]),
+                names(&["rename"]),
+            ],
+            stages
+        );
+    }
+
+    #[test]
+    fn test_schedule_reorders_dependencies() {
+        let registry = ModuleRegistry::with_builtin_modules();
+        let modules = names(&["rename", "download", "fetch_song_info"]);
+
+        let stages = schedule_modules(&modules, &registry).unwrap();
+
+        assert_eq!(
+            vec![
+                names(&["fetch_song_info"]),
+                names(&["download"]),
+                names(&["rename"]),
+            ],
+            stages
+        );
+    }
+
+    #[test]
+    fn test_schedule_module_instances() {
+        let registry = ModuleRegistry::with_builtin_modules();
+        let modules = names(&[
+            "fetch_song_info",
+            "infocopy",
+            "infocopy@fallback",
+            "download",
+            "rename",
+            "rename@copy",
+        ]);
+
+        let stages = schedule_modules(&modules, &registry).unwrap();
+
+        assert_eq!(
+            vec![
+                names(&["fetch_song_info"]),
+                names(&["infocopy"]),
+                names(&["infocopy@fallback", "download"]),
+                names(&["rename"]),
+                names(&["rename@copy"]),
+            ],
+            stages
+        );
+    }
+
+    #[test]
+    fn test_schedule_missing_dependency() {
+        let registry = ModuleRegistry::with_builtin_modules();
+
+        assert!(schedule_modules(&names(&["download"]), &registry).is_err());
+        assert!(schedule_modules(&names(&["nonexistent"]), &registry).is_err());
+    }
+
+    #[test]
+    fn test_sparse_stages() {
+        let config: Config = serde_json::from_value(json!({
+            "stages": {
+                "stage20": ["rename"],
+                "stage10": ["fetch_song_info"],
+                "stage15": ["download"],
+                "stage2": ["infocopy"],
+            }
+        }))
+        .unwrap();
+
+        let numbers: Vec<u32> = get_explicit_stages(&config)
+            .into_iter()
+            .map(|(number, _)| number)
+            .collect();
+        assert_eq!(vec![2, 10, 15, 20], numbers);
+
+        assert_eq!(Some(99), stage_number("stage99"));
+        assert_eq!(None, stage_number("stage100"));
+        assert_eq!(None, stage_number("stage0"));
+        assert_eq!(None, stage_number("stage05"));
+        assert_eq!(None, stage_number("stage+5"));
+        assert_eq!(None, stage_number("stages"));
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A song as it is passed from module to module
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Song {
+    /// The info json yt-dlp printed for this song
+    #[serde(default)]
+    pub yt_dlp: Value,
+    /// The metadata that will end up in the tags and filename of the song
+    #[serde(default)]
+    pub songinfo: SongInfo,
+    /// Metadata given ahead of time in the batch file or on the command line. Infocopy merges it
+    /// over the songinfo after copying the yt-dlp fields, so it wins over the yt-dlp json
+    #[serde(default)]
+    pub preset: SongInfo,
+    #[serde(default)]
+    pub status: SongStatus,
+}
+
+impl Song {
+    /// Creates a song from a single line of `yt-dlp -j` output
+    pub fn from_yt_dlp_json(json: &str) -> Result<Song> {
+        let yt_dlp: Value = serde_json::from_str(json)
+            .with_context(|| format!("yt-dlp outputted invalid JSON: \n {}", json))?;
+
+        if !yt_dlp.is_object() {
+            bail!("yt-dlp outputted JSON that is not an object: \n {}", json);
+        }
+
+        Ok(Song {
+            yt_dlp,
+            songinfo: SongInfo::default(),
+            preset: SongInfo::default(),
+            status: SongStatus::Ok,
+        })
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == SongStatus::Ok
+    }
+}
+
+/// Whether a song is still being processed. Modules skip songs that are not `Ok`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SongStatus {
+    #[default]
+    Ok,
+    Failed {
+        module: String,
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SongInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_no: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_tracks: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext: Option<String>,
+    /// Fields that are not known to musicfetch. Modules can use these to pass data to each other
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl SongInfo {
+    /// Returns the value of a field by its name. Fields that are not set return `None`
+    pub fn get_field(&self, field: &str) -> Option<Value> {
+        let Ok(Value::Object(mut fields)) = serde_json::to_value(self) else {
+            return None;
+        };
+        fields.remove(field).filter(|v| !v.is_null())
+    }
+
+    /// Sets a field by its name. Fails if the value has the wrong type for the field
+    pub fn set_field(&mut self, field: &str, value: Value) -> Result<()> {
+        let Value::Object(mut fields) = serde_json::to_value(&*self)? else {
+            bail!("Songinfo could not be serialized to an object");
+        };
+        fields.insert(field.to_owned(), value.clone());
+
+        *self = serde_json::from_value(Value::Object(fields))
+            .with_context(|| format!("Invalid value {} for songinfo field '{}'", value, field))?;
+        Ok(())
+    }
+
+    /// Sets every field that is set in `other`. Fields that `other` doesn't set are kept
+    pub fn merge(&mut self, other: &SongInfo) -> Result<()> {
+        let Value::Object(fields) = serde_json::to_value(other)? else {
+            bail!("Songinfo could not be serialized to an object");
+        };
+        for (field, value) in fields {
+            self.set_field(&field, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::SongInfo;
+
+    #[test]
+    fn test_set_field() {
+        let mut songinfo = SongInfo::default();
+
+        songinfo.set_field("year", json!(1994)).unwrap();
+        songinfo.set_field("title", json!("Test Song")).unwrap();
+        songinfo.set_field("label", json!("Testlabel")).unwrap();
+
+        assert_eq!(Some(1994), songinfo.year);
+        assert_eq!(Some(String::from("Test Song")), songinfo.title);
+        assert_eq!(Some(json!("Testlabel")), songinfo.get_field("label"));
+
+        assert!(songinfo.set_field("year", json!("nineteen")).is_err());
+        assert_eq!(Some(1994), songinfo.year);
+
+        songinfo.set_field("year", json!(null)).unwrap();
+        assert_eq!(None, songinfo.get_field("year"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut songinfo = SongInfo {
+            title: Some(String::from("Title")),
+            album: Some(String::from("From yt-dlp")),
+            ..Default::default()
+        };
+        let mut preset = SongInfo {
+            album: Some(String::from("From the batch file")),
+            year: Some(2019),
+            ..Default::default()
+        };
+        preset
+            .set_field("cover_url", json!("https://example.com/x.jpg"))
+            .unwrap();
+
+        songinfo.merge(&preset).unwrap();
+
+        assert_eq!(Some(String::from("Title")), songinfo.title);
+        assert_eq!(Some(String::from("From the batch file")), songinfo.album);
+        assert_eq!(Some(2019), songinfo.year);
+        assert!(songinfo.get_field("cover_url").is_some());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    global::GlobalContext,
+    song::{Song, SongStatus},
+};
+
+/// Where the state of a run is saved if no other file is given with --resume
+pub const DEFAULT_STATE_FILE: &str = ".musicfetch-state.json";
+
+/// Everything needed to continue a run that did not finish
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunState {
+    /// The first stage in which a module failed or was cancelled. Songs that failed on their own
+    /// don't hold the run back, they are retried from the stage they failed in
+    pub next_stage: usize,
+    pub stages: Vec<Vec<String>>,
+    pub global: GlobalContext,
+    pub songs: Vec<Song>,
+}
+
+impl RunState {
+    pub fn load(path: &Path) -> Result<RunState> {
+        let state_string = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file {}", path.display()))?;
+
+        serde_json::from_str(&state_string)
+            .with_context(|| format!("Invalid state file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let state_string = serde_json::to_string_pretty(self)?;
+
+        // Write to a temporary file first so a crash while saving doesn't destroy the old state
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, state_string)
+            .with_context(|| format!("Failed to write state file {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write state file {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Returns the stage to continue with and the songs to continue with.
+    ///
+    /// The run continues with the stage that held it back or the first stage a song failed in,
+    /// whichever comes first. Songs that failed in that stage or a later one get another try.
+    /// Songs that failed in the first stage have nothing to continue with and stay failed.
+    /// If the run has to start from the beginning the songs are fetched again, so none are returned
+    pub fn resume_point(self, stages: &[Vec<String>]) -> Result<(usize, Vec<Song>)> {
+        if self.stages != stages {
+            bail!("The modules in the config changed since the state was saved. The run can't be resumed");
+        }
+        if self.next_stage == 0 {
+            return Ok((0, vec![]));
+        }
+
+        let failed_stage = |song: &Song| {
+            let SongStatus::Failed { module, .. } = &song.status else {
+                return None;
+            };
+            stages
+                .iter()
+                .position(|stage| stage.contains(module))
+                .filter(|stage| *stage > 0)
+        };
+        let next_stage = self
+            .songs
+            .iter()
+            .filter_map(failed_stage)
+            .fold(self.next_stage, usize::min);
+
+        let mut songs = self.songs;
+        for song in &mut songs {
+            if failed_stage(song).is_some_and(|stage| stage >= next_stage) {
+                song.status = SongStatus::Ok;
+            }
+        }
+        Ok((next_stage, songs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::RunState;
+
+    #[test]
+    fn test_resume_point() {
+        let stages: Vec<Vec<String>> = serde_json::from_value(json!([
+            ["fetch_song_info"],
+            ["download"],
+            ["rename"]
+        ]))
+        .unwrap();
+        let state: RunState = serde_json::from_value(json!({
+            "next_stage": 2,
+            "stages": stages,
+            "global": { "args": {}, "config": {} },
+            "songs": [
+                { "songinfo": { "title": "Renamed" } },
+                { "status": { "state": "failed", "module": "download", "error": "" } },
+                { "status": { "state": "failed", "module": "rename", "error": "" } },
+            ],
+        }))
+        .unwrap();
+
+        let (next_stage, songs) = state.resume_point(&stages).unwrap();
+
+        // The song that failed to download is tried again, so the run goes back to its stage
+        assert_eq!(1, next_stage);
+        assert!(songs[0].is_ok());
+        assert!(songs[1].is_ok());
+        assert!(songs[2].is_ok());
+    }
+
+    #[test]
+    fn test_resume_failed_songs() {
+        let stages: Vec<Vec<String>> = serde_json::from_value(json!([
+            ["fetch_song_info"],
+            ["tagui"],
+            ["download"],
+            ["rename"]
+        ]))
+        .unwrap();
+        // A deleted video failed in the first stage, a download failed and then rename failed
+        let state: RunState = serde_json::from_value(json!({
+            "next_stage": 3,
+            "stages": stages,
+            "global": { "args": {}, "config": {} },
+            "songs": [
+                { "status": { "state": "failed", "module": "fetch_song_info", "error": "" } },
+                { "songinfo": { "title": "Typed in tagui" } },
+                { "status": { "state": "failed", "module": "download", "error": "" } },
+            ],
+        }))
+        .unwrap();
+
+        let (next_stage, songs) = state.resume_point(&stages).unwrap();
+
+        assert_eq!(2, next_stage);
+        assert!(!songs[0].is_ok());
+        assert_eq!(
+            Some(String::from("Typed in tagui")),
+            songs[1].songinfo.title
+        );
+        assert!(songs[2].is_ok());
+    }
+}
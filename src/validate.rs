@@ -0,0 +1,358 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::{
+    config::Config,
+    config_sources::{ConfigSources, Location},
+    logging,
+    modules::{module_name_of, Module, ModuleRegistry},
+    schedule,
+};
+
+/// Checks the config before anything is run. All problems that are found are reported in one error
+pub fn validate_config(config: &Config, registry: &ModuleRegistry) -> Result<()> {
+    let mut problems = vec![];
+
+    let module_names: Vec<&String> = if config.stages.is_empty() {
+        config.modules.iter().collect()
+    } else {
+        config.stages.values().flatten().collect()
+    };
+
+    if module_names.is_empty() {
+        problems.push(String::from("The config does not contain any modules"));
+    }
+
+    for (i, name) in module_names.iter().enumerate() {
+        let location = list_location(config, name);
+        if registry.get(name).is_none() {
+            problems.push(at(location, unknown_module_message(name, registry)));
+        }
+        if name.ends_with('@') {
+            problems.push(at(
+                location,
+                format!("Instance name '{name}' is missing a name after the '@'"),
+            ));
+        }
+        if module_names[..i].contains(name) {
+            problems.push(at(location, format!(
+                "Module {name} is listed more than once. To run it again with other settings use an instance name like {}@second",
+                module_name_of(name)
+            )));
+        }
+    }
+
+    if config.stages.is_empty() {
+        check_dependencies_present(config, registry, &mut problems);
+    } else {
+        for key in config.stages.keys() {
+            if schedule::stage_number(key).is_none() {
+                problems.push(at(
+                    config.sources.key(&["stages", key]),
+                    format!(
+                        "Invalid stage name '{key}'. Stages have to be named stage1 to stage{}",
+                        schedule::MAX_STAGE
+                    ),
+                ));
+            }
+        }
+        check_stage_order(
+            &schedule::get_explicit_stages(config),
+            &config.sources,
+            registry,
+            &mut problems,
+        );
+        warn_unstaged_modules(config);
+    }
+
+    // Settings for a module that doesn't exist are most likely a typo in the table name
+    for name in config.module.keys() {
+        if registry.get(name).is_none() {
+            problems.push(at(
+                config.sources.key(&["module", name]),
+                format!(
+                    "Settings for unknown module: {}",
+                    unknown_module_message(name, registry)
+                ),
+            ));
+        }
+    }
+
+    for (i, name) in module_names.iter().enumerate() {
+        let Some(module) = registry.get(name) else {
+            continue;
+        };
+        if module_names[..i].contains(name) {
+            continue;
+        }
+
+        check_module_config(
+            name,
+            module.as_ref(),
+            config.module_config(name),
+            &config.sources,
+            &mut problems,
+        );
+    }
+
+    let log_levels =
+        config
+            .logging
+            .level
+            .iter()
+            .map(|level| (level, config.sources.value(&["logging", "level"])))
+            .chain(config.logging.modules.iter().map(|(module, level)| {
+                (level, config.sources.value(&["logging", "modules", module]))
+            }));
+    for (level, location) in log_levels {
+        if let Err(error) = logging::parse_level(level) {
+            problems.push(at(location, error.to_string()));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "Found {} problem(s) in the config:\n  - {}",
+            problems.len(),
+            problems.join("\n  - ")
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn unknown_module_message(name: &str, registry: &ModuleRegistry) -> String {
+    let name = module_name_of(name);
+    let suggestion = registry
+        .modules()
+        .map(|module| (levenshtein(name, module.name()), module.name()))
+        .filter(|(distance, module_name)| *distance <= (module_name.len() / 3).max(2))
+        .min();
+
+    match suggestion {
+        Some((_, module_name)) => {
+            format!("No module named '{name}'. Did you mean '{module_name}'?")
+        }
+        None => format!("No module named '{name}'"),
+    }
+}
+
+/// With explicit stages the `modules` list is ignored. It usually comes from the config that is
+/// extended, so modules that are in it but in no stage are pointed out instead of being dropped silently
+fn warn_unstaged_modules(config: &Config) {
+    let staged: Vec<&String> = config.stages.values().flatten().collect();
+    let unstaged: Vec<&str> = config
+        .modules
+        .iter()
+        .filter(|name| !staged.contains(name))
+        .map(String::as_str)
+        .collect();
+
+    if !unstaged.is_empty() {
+        log::warn!(
+            "{}",
+            at(
+                config.sources.key(&["stages"]),
+                format!(
+                    "The config has [stages], so these modules from the modules list don't run: {}. Add them to a stage or remove them from modules",
+                    unstaged.join(", ")
+                ),
+            )
+        );
+    }
+}
+
+/// Puts the location in the config file in front of a problem if it is known
+pub(crate) fn at(location: Option<&Location>, problem: String) -> String {
+    match location {
+        Some(location) => format!("{location}: {problem}"),
+        None => problem,
+    }
+}
+
+/// The location of the list of modules or the stage a module is listed in
+fn list_location<'a>(config: &'a Config, name: &str) -> Option<&'a Location> {
+    if config.stages.is_empty() {
+        return config.sources.value(&["modules"]);
+    }
+    let (stage, _) = config
+        .stages
+        .iter()
+        .find(|(_, modules)| modules.iter().any(|module| module == name))?;
+    config.sources.value(&["stages", stage])
+}
+
+fn check_dependencies_present(
+    config: &Config,
+    registry: &ModuleRegistry,
+    problems: &mut Vec<String>,
+) {
+    let module_names = &config.modules;
+    for name in module_names {
+        let Some(module) = registry.get(name) else {
+            continue;
+        };
+        for dependency in module.dependencies() {
+            if !module_names
+                .iter()
+                .any(|m| module_name_of(m) == *dependency)
+            {
+                problems.push(at(config.sources.value(&["modules"]), format!(
+                    "Module {name} depends on {dependency} but {dependency} is not in the list of modules"
+                )));
+            }
+        }
+    }
+}
+
+fn check_stage_order(
+    stages: &[(u32, Vec<String>)],
+    sources: &ConfigSources,
+    registry: &ModuleRegistry,
+    problems: &mut Vec<String>,
+) {
+    for (i, (stage_number, stage)) in stages.iter().enumerate() {
+        let location = sources.value(&["stages", &format!("stage{stage_number}")]);
+        for name in stage {
+            let Some(module) = registry.get(name) else {
+                continue;
+            };
+            for dependency in module.dependencies() {
+                let is_dependency = |m: &String| module_name_of(m) == *dependency;
+
+                if stages[..i]
+                    .iter()
+                    .flat_map(|(_, stage)| stage)
+                    .any(is_dependency)
+                {
+                    continue;
+                }
+
+                match stages.iter().find(|(_, s)| s.iter().any(is_dependency)) {
+                    Some((dependency_stage, _)) => problems.push(at(location, format!(
+                        "Module {name} in stage{stage_number} depends on {dependency} which runs in stage{dependency_stage}. Please move {dependency} to an earlier stage."
+                    ))),
+                    None => problems.push(at(location, format!(
+                        "Module {name} depends on {dependency} but {dependency} is not in any stage"
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+fn check_module_config(
+    name: &str,
+    module: &dyn Module,
+    config: Option<&Value>,
+    sources: &ConfigSources,
+    problems: &mut Vec<String>,
+) {
+    let empty = serde_json::Map::new();
+    let settings = match config {
+        None => &empty,
+        Some(Value::Object(settings)) => settings,
+        Some(_) => {
+            problems.push(at(
+                sources.value(&["module", name]),
+                format!("The settings for module {name} have to be a table"),
+            ));
+            return;
+        }
+    };
+
+    let options = module.config_options();
+
+    for option in &options {
+        match settings.get(option.key) {
+            None if option.required => problems.push(at(
+                sources.key(&["module", name]),
+                format!(
+                    "Module {name} is missing the required setting '{}'",
+                    option.key
+                ),
+            )),
+            Some(value) if !option.value_type.matches(value) => problems.push(at(
+                sources.value(&["module", name, option.key]),
+                format!(
+                    "Setting '{}' of module {name} has to be of type {} but is {}",
+                    option.key, option.value_type, value
+                ),
+            )),
+            _ => {}
+        }
+    }
+
+    for (key, value) in settings {
+        if options.iter().any(|option| option.key == key) {
+            continue;
+        }
+        match module.additional_config_values() {
+            None => problems.push(at(
+                sources.key(&["module", name, key]),
+                format!("Module {name} has no setting '{key}'"),
+            )),
+            Some(value_type) if !value_type.matches(value) => problems.push(at(
+                sources.value(&["module", name, key]),
+                format!(
+                    "Setting '{key}' of module {name} has to be of type {value_type} but is {value}"
+                ),
+            )),
+            Some(_) => {}
+        }
+    }
+}
+
+/// The number of single character edits needed to turn `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{config::Config, modules::ModuleRegistry};
+
+    use super::{levenshtein, validate_config};
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(0, levenshtein("rename", "rename"));
+        assert_eq!(1, levenshtein("renam", "rename"));
+        assert_eq!(2, levenshtein("tagiu", "tagui"));
+    }
+
+    #[test]
+    fn test_all_problems_reported() {
+        let registry = ModuleRegistry::with_builtin_modules();
+        let config: Config = serde_json::from_value(json!({
+            "stages": {
+                "stage1": ["download", "renam"],
+                "stage2": ["fetch_song_info"],
+                "stage100": ["tag_files"],
+            },
+            "module": {
+                "download": { "yt_dlp_args": "--audio-format mp3" },
+            },
+        }))
+        .unwrap();
+
+        let error = validate_config(&config, &registry).unwrap_err().to_string();
+
+        assert!(error.contains("Did you mean 'rename'?"));
+        assert!(error.contains("Please move fetch_song_info to an earlier stage"));
+        assert!(error.contains("'yt_dlp_args' of module download"));
+        assert!(error.contains("Invalid stage name 'stage100'"));
+    }
+}